@@ -0,0 +1,870 @@
+use crate::constants::ID_APPLICATION;
+use crate::secret::SecretString;
+use anyhow::{anyhow, Context};
+use sequoia_openpgp::crypto::mem::Protected;
+use sequoia_openpgp::crypto::SessionKey;
+use sequoia_openpgp::fmt::hex;
+use sequoia_openpgp::packet::{key, Key, PKESK};
+use sequoia_openpgp::parse::Parse;
+use sequoia_openpgp::serialize::MarshalInto;
+use sequoia_openpgp::types::SymmetricAlgorithm;
+use sequoia_openpgp::{Fingerprint, Packet};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type SecretKey = Key<key::SecretParts, key::UnspecifiedRole>;
+
+/// Time a client has to send its request, an idle client can not block the agent longer
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time the agent has to answer, it may be busy with another client
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest request accepted, keys and encrypted session keys are much smaller
+const MAX_REQUEST_LENGTH: u64 = 64 * 1024;
+
+/// Unlocked key kept by the agent
+struct CachedKey {
+    key: SecretKey,
+
+    // Keys are forgotten when they are not used for a while
+    last_used: Instant,
+}
+
+type Cache = Arc<Mutex<HashMap<Fingerprint, CachedKey>>>;
+
+#[cfg(unix)]
+type Stream = std::os::unix::net::UnixStream;
+
+#[cfg(unix)]
+type Listener = std::os::unix::net::UnixListener;
+
+/// A named pipe is a file on Windows
+#[cfg(windows)]
+type Stream = std::fs::File;
+
+/// Name of the socket of the agent, a named pipe on Windows
+#[cfg(windows)]
+fn socket_name() -> anyhow::Result<String> {
+    // Named pipes are shared between users of the machine,
+    // the agent and its clients make sure they are run by the same user
+    let user = std::env::var("USERNAME").unwrap_or_default();
+
+    Ok(format!("{}-agent-{}", ID_APPLICATION, user))
+}
+
+/// Name of the socket of the agent, a unix domain socket in a private folder of the user
+#[cfg(unix)]
+fn socket_name() -> anyhow::Result<String> {
+    use crate::constants::{ID_ORGANIZATION, ID_QUALIFIER};
+    use directories_next::ProjectDirs;
+
+    let proj_dir = ProjectDirs::from(ID_QUALIFIER, ID_ORGANIZATION, ID_APPLICATION)
+        .context("Failed to find a folder for the socket of the agent")?;
+    let folder = proj_dir
+        .runtime_dir()
+        .unwrap_or_else(|| proj_dir.config_dir())
+        .join("agent");
+    private_folder(&folder)?;
+
+    Ok(folder.join("socket").to_string_lossy().into_owned())
+}
+
+/// Create `folder` so only the user can use it, or make sure it already is
+///
+/// Nobody else can create a socket in it or connect to the socket of the agent
+#[cfg(unix)]
+fn private_folder(folder: &std::path::Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    if !folder.is_dir() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(folder)
+            .with_context(|| format!("Failed to create folder {}", folder.display()))?;
+    }
+
+    let metadata = std::fs::symlink_metadata(folder)?;
+    if !metadata.is_dir() || metadata.uid() != unsafe { libc::getuid() } {
+        return Err(anyhow!(
+            "{} is not a folder of the user, refusing to use it for the agent",
+            folder.display()
+        ));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(folder, std::fs::Permissions::from_mode(0o700))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect(name: &str) -> io::Result<Stream> {
+    Stream::connect(name)
+}
+
+/// Connect to the pipe `name`, after making sure the agent is run by the user
+#[cfg(windows)]
+fn connect(name: &str) -> anyhow::Result<Stream> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::shared::winerror::ERROR_PIPE_BUSY;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcess};
+    use winapi::um::securitybaseapi::EqualSid;
+    use winapi::um::winbase::{GetNamedPipeServerProcessId, SECURITY_IDENTIFICATION};
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    let mut attempts = 0;
+    let pipe = loop {
+        // The agent can not impersonate us
+        let result = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .security_qos_flags(SECURITY_IDENTIFICATION)
+            .open(pipe_path(name));
+
+        match result {
+            Ok(pipe) => break pipe,
+            // Agent is answering another client
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) && attempts < 20 => {
+                attempts += 1;
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    // Anybody can create a pipe with the name of the agent
+    let mut process_id = 0;
+    if unsafe { GetNamedPipeServerProcessId(pipe.as_raw_handle() as _, &mut process_id) } == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id) };
+    if process.is_null() {
+        return Err(io::Error::last_os_error().into());
+    }
+    let agent_user = process_user(process);
+    unsafe { CloseHandle(process) };
+
+    let user = process_user(unsafe { GetCurrentProcess() })?;
+    if unsafe { EqualSid(user_sid(&agent_user?), user_sid(&user)) } == 0 {
+        return Err(anyhow!("Agent is run by another user, refusing to use it"));
+    }
+
+    Ok(pipe)
+}
+
+#[cfg(windows)]
+fn pipe_path(name: &str) -> String {
+    format!(r"\\.\pipe\{}", name)
+}
+
+/// Null terminated UTF-16 string for Windows
+#[cfg(windows)]
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// User running `process`, as a `TOKEN_USER` structure followed by the SID of the user
+#[cfg(windows)]
+fn process_user(process: winapi::um::winnt::HANDLE) -> io::Result<Vec<u64>> {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::OpenProcessToken;
+    use winapi::um::securitybaseapi::GetTokenInformation;
+    use winapi::um::winnt::{TokenUser, TOKEN_QUERY};
+
+    let mut token = std::ptr::null_mut();
+    if unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // `u64` to be aligned like `TOKEN_USER`, large enough for the longest SID
+    let mut user = vec![0u64; 64];
+    let mut length = 0;
+    let result = unsafe {
+        GetTokenInformation(
+            token,
+            TokenUser,
+            user.as_mut_ptr() as _,
+            (user.len() * std::mem::size_of::<u64>()) as u32,
+            &mut length,
+        )
+    };
+    let error = io::Error::last_os_error();
+    unsafe { CloseHandle(token) };
+
+    if result == 0 {
+        return Err(error);
+    }
+    Ok(user)
+}
+
+#[cfg(windows)]
+fn user_sid(user: &[u64]) -> winapi::um::winnt::PSID {
+    unsafe {
+        (*(user.as_ptr() as *const winapi::um::winnt::TOKEN_USER))
+            .User
+            .Sid
+    }
+}
+
+/// Named pipe of the agent, there is a new instance of the pipe for each client
+#[cfg(windows)]
+struct Listener {
+    name: Vec<u16>,
+
+    // Only gives access to the user running the agent
+    security_descriptor: winapi::um::winnt::PSECURITY_DESCRIPTOR,
+
+    // Instance waiting for the next client
+    pipe: Stream,
+}
+
+// Security descriptor is memory owned by the listener, it can be freed from any thread
+#[cfg(windows)]
+unsafe impl Send for Listener {}
+
+#[cfg(windows)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        unsafe { winapi::um::winbase::LocalFree(self.security_descriptor) };
+    }
+}
+
+#[cfg(windows)]
+fn create_pipe(
+    name: &[u16],
+    security_descriptor: winapi::um::winnt::PSECURITY_DESCRIPTOR,
+    first: bool,
+) -> io::Result<Stream> {
+    use std::os::windows::io::FromRawHandle;
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::minwinbase::SECURITY_ATTRIBUTES;
+    use winapi::um::namedpipeapi::CreateNamedPipeW;
+    use winapi::um::winbase::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE,
+        PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: security_descriptor,
+        bInheritHandle: 0,
+    };
+
+    // The first instance fails if somebody else already has a pipe with the name
+    let first = if first {
+        FILE_FLAG_FIRST_PIPE_INSTANCE
+    } else {
+        0
+    };
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            PIPE_ACCESS_DUPLEX | first,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            4096,
+            4096,
+            0,
+            &mut attributes,
+        )
+    };
+    if pipe == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { Stream::from_raw_handle(pipe as _) })
+}
+
+/// Create the socket of the agent, only the user can connect to it
+#[cfg(windows)]
+fn bind(name: &str) -> anyhow::Result<Listener> {
+    use winapi::shared::sddl::{
+        ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW,
+        SDDL_REVISION_1,
+    };
+    use winapi::um::processthreadsapi::GetCurrentProcess;
+    use winapi::um::winbase::LocalFree;
+
+    let user = process_user(unsafe { GetCurrentProcess() })?;
+    let mut sid = std::ptr::null_mut();
+    if unsafe { ConvertSidToStringSidW(user_sid(&user), &mut sid) } == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let sid_string = unsafe {
+        let length = (0..).take_while(|&i| *sid.add(i) != 0).count();
+        String::from_utf16_lossy(std::slice::from_raw_parts(sid, length))
+    };
+    unsafe { LocalFree(sid as _) };
+
+    // Protected DACL which only allows the user, nothing is inherited
+    let sddl = wide(&format!("D:P(A;;GA;;;{})", sid_string));
+    let mut security_descriptor = std::ptr::null_mut();
+    if unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            SDDL_REVISION_1 as u32,
+            &mut security_descriptor,
+            std::ptr::null_mut(),
+        )
+    } == 0
+    {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let name = wide(&pipe_path(name));
+    match create_pipe(&name, security_descriptor, true) {
+        Ok(pipe) => Ok(Listener {
+            name,
+            security_descriptor,
+            pipe,
+        }),
+        Err(e) => {
+            unsafe { LocalFree(security_descriptor) };
+            Err(e).context("Failed to create socket of the agent")
+        }
+    }
+}
+
+/// Wait for a client on the current instance of the pipe, and create the next one
+#[cfg(windows)]
+fn accept(listener: &mut Listener) -> io::Result<Stream> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+    use winapi::um::namedpipeapi::ConnectNamedPipe;
+
+    // Client may have connected as soon as the pipe was created
+    if unsafe { ConnectNamedPipe(listener.pipe.as_raw_handle() as _, std::ptr::null_mut()) } == 0 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+            return Err(error);
+        }
+    }
+
+    let next = create_pipe(&listener.name, listener.security_descriptor, false)?;
+    Ok(std::mem::replace(&mut listener.pipe, next))
+}
+
+/// Number of bytes which can be read from `pipe`, `None` once the other side closed it
+#[cfg(windows)]
+fn pipe_available(pipe: &Stream) -> io::Result<Option<u32>> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::shared::winerror::ERROR_BROKEN_PIPE;
+    use winapi::um::namedpipeapi::PeekNamedPipe;
+
+    let mut available = 0;
+    let result = unsafe {
+        PeekNamedPipe(
+            pipe.as_raw_handle() as _,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            &mut available,
+            std::ptr::null_mut(),
+        )
+    };
+    if result == 0 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) {
+            return Ok(None);
+        }
+        return Err(error);
+    }
+
+    Ok(Some(available))
+}
+
+/// Create the socket of the agent, only the user can connect to it
+#[cfg(unix)]
+fn bind(name: &str) -> anyhow::Result<Listener> {
+    // Socket left by an agent which did not stop properly
+    if std::path::Path::new(name).exists() {
+        if connect(name).is_ok() {
+            return Err(anyhow!("Agent is already running"));
+        }
+        std::fs::remove_file(name)?;
+    }
+
+    // Socket is created only accessible to the user, there is no time when others can connect
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = Listener::bind(name);
+    unsafe { libc::umask(umask) };
+
+    listener.context("Failed to create socket of the agent")
+}
+
+#[cfg(unix)]
+fn accept(listener: &mut Listener) -> io::Result<Stream> {
+    listener.accept().map(|(stream, _)| stream)
+}
+
+/// Stream between the agent and a client, reading fails once `deadline` is passed
+struct Connection {
+    stream: Stream,
+    deadline: Instant,
+}
+
+impl Connection {
+    fn new(stream: Stream, timeout: Duration) -> io::Result<Self> {
+        #[cfg(unix)]
+        stream.set_write_timeout(Some(timeout))?;
+
+        Ok(Self {
+            stream,
+            deadline: Instant::now() + timeout,
+        })
+    }
+
+    fn remaining(&self) -> io::Result<Duration> {
+        match self.deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(remaining),
+            _ => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Connection timed out",
+            )),
+        }
+    }
+
+    /// Wait for the client to close the pipe, closing it first would discard the response
+    #[cfg(windows)]
+    fn wait_closed(&self) -> io::Result<()> {
+        while pipe_available(&self.stream)?.is_some() {
+            self.remaining()?;
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for Connection {
+    #[cfg(unix)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        self.stream.read(buf)
+    }
+
+    #[cfg(windows)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Reading a pipe can not time out, wait until there is something to read
+        loop {
+            match pipe_available(&self.stream)? {
+                None => return Ok(0),
+                Some(0) => {
+                    self.remaining()?;
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Some(_) => return self.stream.read(buf),
+            }
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Send `request` to the agent listening on `name` and return its response
+fn request_to(name: &str, request: &str) -> anyhow::Result<String> {
+    let stream = connect(name).context("Agent is not running")?;
+    let mut connection = Connection::new(stream, REQUEST_TIMEOUT)?;
+    connection.write_all(request.as_bytes())?;
+    connection.write_all(b"\n")?;
+
+    let mut response = String::new();
+    BufReader::new(connection).read_line(&mut response)?;
+
+    Ok(response.trim_end().to_string())
+}
+
+/// Send `request` to the agent and return its response
+fn request(request: &str) -> anyhow::Result<String> {
+    request_to(&socket_name()?, request)
+}
+
+/// Send `command` to the agent, which only answers if it succeeded
+fn command(command: &str) -> anyhow::Result<()> {
+    match request(command)?.as_str() {
+        "OK" => Ok(()),
+        response => Err(anyhow!("Unexpected response from agent: {}", response)),
+    }
+}
+
+pub fn is_running() -> bool {
+    command("PING").is_ok()
+}
+
+/// Ask the agent to decrypt the session key of `pkesk` with its unlocked key `fingerprint`
+///
+/// The agent never gives its keys back. Returns `None` if the agent
+/// is not running, does not have the key or could not decrypt the session key
+pub fn decrypt(
+    fingerprint: &Fingerprint,
+    pkesk: &PKESK,
+    sym_algo: Option<SymmetricAlgorithm>,
+) -> Option<(SymmetricAlgorithm, SessionKey)> {
+    let response = request(&format!(
+        "DECRYPT {} {} {}",
+        fingerprint.to_hex(),
+        encode_algo(sym_algo),
+        encode_pkesk(pkesk).ok()?
+    ))
+    .ok()?;
+
+    decode_session_key(response.strip_prefix("SESSIONKEY ")?).ok()
+}
+
+/// Give unlocked `key` to the agent
+pub fn put_key(key: &SecretKey) -> anyhow::Result<()> {
+    let encoded = encode_key(key)?;
+    let mut request = SecretString::with_capacity(encoded.len() + 4);
+    request.push_str("PUT ");
+    request.push_str(&encoded);
+
+    command(&request)
+}
+
+/// Make the agent forget all keys
+pub fn lock() -> anyhow::Result<()> {
+    command("LOCK")
+}
+
+pub fn stop() -> anyhow::Result<()> {
+    command("STOP")
+}
+
+/// Unlocked key as hex, it is only kept in locked memory wiped on drop
+fn encode_key(key: &SecretKey) -> anyhow::Result<SecretString> {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+    let packet = Packet::from(key.clone().role_into_subordinate());
+    let bytes = Protected::from(packet.to_vec()?);
+
+    let mut encoded = SecretString::with_capacity(bytes.len() * 2);
+    for byte in bytes.iter() {
+        encoded.push(DIGITS[usize::from(byte >> 4)] as char);
+        encoded.push(DIGITS[usize::from(byte & 0x0f)] as char);
+    }
+
+    Ok(encoded)
+}
+
+fn decode_key(s: &str) -> anyhow::Result<SecretKey> {
+    let bytes = Protected::from(hex::decode(s)?);
+
+    match Packet::from_bytes(&bytes[..])? {
+        Packet::SecretSubkey(key) => Ok(key.role_into_unspecified()),
+        _ => Err(anyhow!("Failed to parse key")),
+    }
+}
+
+fn encode_pkesk(pkesk: &PKESK) -> anyhow::Result<String> {
+    Ok(hex::encode(Packet::from(pkesk.clone()).to_vec()?))
+}
+
+fn decode_pkesk(s: &str) -> anyhow::Result<PKESK> {
+    match Packet::from_bytes(&hex::decode(s)?)? {
+        Packet::PKESK(pkesk) => Ok(pkesk),
+        _ => Err(anyhow!("Failed to parse encrypted session key")),
+    }
+}
+
+/// Symmetric algorithm as a number, `-` when it is not known
+fn encode_algo(algo: Option<SymmetricAlgorithm>) -> String {
+    match algo {
+        Some(algo) => u8::from(algo).to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn decode_algo(s: &str) -> anyhow::Result<Option<SymmetricAlgorithm>> {
+    match s {
+        "-" => Ok(None),
+        algo => Ok(Some(algo.parse::<u8>()?.into())),
+    }
+}
+
+fn decode_session_key(s: &str) -> anyhow::Result<(SymmetricAlgorithm, SessionKey)> {
+    let (algo, session_key) = s.split_once(' ').context("Failed to parse session key")?;
+    let algo = decode_algo(algo)?.context("Failed to parse session key")?;
+
+    Ok((algo, hex::decode(session_key)?.into()))
+}
+
+/// Answer one request, return `true` if the agent has to stop
+///
+/// A request which can not be handled is answered with `ERR` and the reason
+fn handle(connection: &mut Connection, cache: &Cache) -> anyhow::Result<bool> {
+    // Request may contain a key, the buffer is big enough to never be moved
+    let mut line = String::with_capacity(MAX_REQUEST_LENGTH as usize);
+    BufReader::new(connection.take(MAX_REQUEST_LENGTH)).read_line(&mut line)?;
+    let line = SecretString::from(line);
+
+    let mut stop = false;
+    let response = respond(&line, cache, &mut stop).unwrap_or_else(|e| format!("ERR {:#}", e));
+
+    connection.write_all(format!("{}\n", response).as_bytes())?;
+
+    #[cfg(windows)]
+    connection.wait_closed()?;
+
+    Ok(stop)
+}
+
+/// Response to the request `line`, `stop` is set if the agent has to stop
+fn respond(line: &str, cache: &Cache, stop: &mut bool) -> anyhow::Result<String> {
+    let response = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["PING"] => "OK".to_string(),
+        ["DECRYPT", fingerprint, sym_algo, pkesk] => {
+            let fingerprint = Fingerprint::from_hex(fingerprint)?;
+            let sym_algo = decode_algo(sym_algo)?;
+            let pkesk = decode_pkesk(pkesk)?;
+            let mut cache = cache.lock().unwrap();
+
+            match cache.get_mut(&fingerprint) {
+                Some(cached) => {
+                    cached.last_used = Instant::now();
+                    let mut keypair = cached.key.clone().into_keypair()?;
+
+                    match pkesk.decrypt(&mut keypair, sym_algo) {
+                        Some((algo, session_key)) => format!(
+                            "SESSIONKEY {} {}",
+                            encode_algo(Some(algo)),
+                            hex::encode(&session_key)
+                        ),
+                        None => return Err(anyhow!("Failed to decrypt session key")),
+                    }
+                }
+                None => "NONE".to_string(),
+            }
+        }
+        ["PUT", key] => {
+            let key = decode_key(key)?;
+            if key.secret().is_encrypted() {
+                return Err(anyhow!("Key is not unlocked"));
+            }
+
+            let cached = CachedKey {
+                key,
+                last_used: Instant::now(),
+            };
+            cache
+                .lock()
+                .unwrap()
+                .insert(cached.key.fingerprint(), cached);
+            "OK".to_string()
+        }
+        ["LOCK"] => {
+            cache.lock().unwrap().clear();
+            "OK".to_string()
+        }
+        ["STOP"] => {
+            *stop = true;
+            "OK".to_string()
+        }
+        _ => return Err(anyhow!("Unknown request")),
+    };
+
+    Ok(response)
+}
+
+/// Answer clients connecting to `listener` until the agent is asked to stop
+///
+/// Keys not used for `timeout` are forgotten
+fn run(mut listener: Listener, timeout: Duration) -> anyhow::Result<()> {
+    let cache: Cache = Arc::new(Mutex::new(HashMap::new()));
+
+    // Forget keys once they are idle for too long, until the agent stops
+    let expiring_cache = Arc::downgrade(&cache);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(1));
+        match expiring_cache.upgrade() {
+            Some(cache) => cache
+                .lock()
+                .unwrap()
+                .retain(|_, cached| cached.last_used.elapsed() < timeout),
+            None => break,
+        }
+    });
+
+    loop {
+        // A broken or idle client should not stop the agent
+        match accept(&mut listener)
+            .and_then(|stream| Connection::new(stream, CONNECTION_TIMEOUT))
+            .map_err(anyhow::Error::from)
+            .and_then(|mut connection| handle(&mut connection, &cache))
+        {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the agent until it is asked to stop
+///
+/// Keys not used for `timeout` are forgotten
+pub fn serve(timeout: Duration) -> anyhow::Result<()> {
+    let name = socket_name()?;
+    run(bind(&name)?, timeout)?;
+
+    #[cfg(unix)]
+    std::fs::remove_file(&name)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecretKey;
+    use crate::recipients::load_certs;
+    use sequoia_openpgp::crypto::{Password, SessionKey};
+    use sequoia_openpgp::packet::pkesk::PKESK3;
+    use sequoia_openpgp::packet::PKESK;
+    use sequoia_openpgp::policy::StandardPolicy;
+    use sequoia_openpgp::types::SymmetricAlgorithm;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+    use tempfile::tempdir;
+
+    fn unlocked_key() -> anyhow::Result<SecretKey> {
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let cert = load_certs(secret_key_path.as_ref())?.remove(0);
+
+        let policy = &StandardPolicy::new();
+        let mut key = cert
+            .keys()
+            .with_policy(policy, None)
+            .for_storage_encryption()
+            .secret()
+            .map(|key| key.key().clone())
+            .next()
+            .unwrap();
+        let algo = key.pk_algo();
+        key.secret_mut()
+            .decrypt_in_place(algo, &Password::from("password"))?;
+
+        Ok(key)
+    }
+
+    /// Name of a socket only used by a test
+    #[cfg(unix)]
+    fn test_socket_name(folder: &Path) -> String {
+        folder.join("socket").to_string_lossy().into_owned()
+    }
+
+    #[cfg(windows)]
+    fn test_socket_name(folder: &Path) -> String {
+        let folder = folder.file_name().unwrap().to_string_lossy();
+        format!("{}-test-{}", crate::constants::ID_APPLICATION, folder)
+    }
+
+    #[test]
+    fn encode_decode_key() -> anyhow::Result<()> {
+        let key = unlocked_key()?;
+
+        let decoded = super::decode_key(&super::encode_key(&key)?)?;
+        assert_eq!(decoded.fingerprint(), key.fingerprint());
+        assert_eq!(decoded.secret().is_encrypted(), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn protocol() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let name = test_socket_name(tmp_dir.path());
+        let listener = super::bind(&name)?;
+        let agent = std::thread::spawn(move || super::run(listener, Duration::from_secs(60)));
+
+        assert_eq!(super::request_to(&name, "PING")?, "OK");
+
+        // Session key encrypted for our key
+        let key = unlocked_key()?;
+        let session_key = SessionKey::new(32);
+        let pkesk: PKESK =
+            PKESK3::for_recipient(SymmetricAlgorithm::AES256, &session_key, &key)?.into();
+        let decrypt = format!(
+            "DECRYPT {} - {}",
+            key.fingerprint().to_hex(),
+            super::encode_pkesk(&pkesk)?
+        );
+
+        // Agent does not have the key yet
+        assert_eq!(super::request_to(&name, &decrypt)?, "NONE");
+
+        // Only unlocked keys are accepted
+        let mut locked = key.clone();
+        locked
+            .secret_mut()
+            .encrypt_in_place(&Password::from("password"))?;
+        let put = format!("PUT {}", &*super::encode_key(&locked)?);
+        assert_eq!(super::request_to(&name, &put)?, "ERR Key is not unlocked");
+
+        // Malformed requests are answered with an error too
+        assert_eq!(
+            super::request_to(&name, "PUT 00")?.starts_with("ERR "),
+            true
+        );
+
+        let put = format!("PUT {}", &*super::encode_key(&key)?);
+        assert_eq!(super::request_to(&name, &put)?, "OK");
+
+        // Agent decrypts the session key itself
+        let response = super::request_to(&name, &decrypt)?;
+        let (algo, decrypted) = super::decode_session_key(
+            response
+                .strip_prefix("SESSIONKEY ")
+                .expect("Agent did not decrypt the session key"),
+        )?;
+        assert_eq!(algo, SymmetricAlgorithm::AES256);
+        assert_eq!(&decrypted[..], &session_key[..]);
+
+        // Keys never leave the agent
+        let get = format!("GET {}", key.fingerprint().to_hex());
+        assert_eq!(super::request_to(&name, &get)?.starts_with("ERR"), true);
+
+        assert_eq!(super::request_to(&name, "LOCK")?, "OK");
+        assert_eq!(super::request_to(&name, &decrypt)?, "NONE");
+
+        assert_eq!(super::request_to(&name, "STOP")?, "OK");
+        agent.join().unwrap()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn idle_client() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let name = test_socket_name(tmp_dir.path());
+        let listener = super::bind(&name)?;
+        let agent = std::thread::spawn(move || super::run(listener, Duration::from_secs(60)));
+
+        // Client which connects and never sends its request
+        let idle = super::connect(&name)?;
+
+        // Agent gives up on the idle client and answers the next one
+        let start = Instant::now();
+        assert_eq!(super::request_to(&name, "PING")?, "OK");
+        assert_eq!(start.elapsed() < super::REQUEST_TIMEOUT, true);
+        drop(idle);
+
+        assert_eq!(super::request_to(&name, "STOP")?, "OK");
+        agent.join().unwrap()?;
+
+        Ok(())
+    }
+}
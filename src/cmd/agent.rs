@@ -0,0 +1,65 @@
+use crate::agent;
+use crate::settings::Settings;
+use anyhow::{anyhow, Context};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Start the agent in the background
+pub fn start(settings: &Settings) -> anyhow::Result<()> {
+    if agent::is_running() {
+        println!("Agent is already running");
+        return Ok(());
+    }
+
+    let timeout = settings.get_agent_timeout().as_secs().to_string();
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(["agent", "serve", "--timeout", &timeout])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // Do not attach the agent to the console of the terminal
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x00000008;
+        command.creation_flags(DETACHED_PROCESS);
+    }
+
+    command.spawn().context("Failed to start agent")?;
+
+    // Wait for the agent to listen
+    for _ in 0..20 {
+        if agent::is_running() {
+            println!(
+                "Agent started, keys are forgotten after {} seconds without use",
+                timeout
+            );
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    Err(anyhow!("Agent did not start"))
+}
+
+/// Run the agent in the foreground
+pub fn serve(timeout: u64) -> anyhow::Result<()> {
+    agent::serve(Duration::from_secs(timeout))
+}
+
+pub fn stop() -> anyhow::Result<()> {
+    agent::stop()?;
+    println!("Agent stopped");
+
+    Ok(())
+}
+
+/// Make the agent forget the unlocked keys
+pub fn lock() -> anyhow::Result<()> {
+    agent::lock()?;
+    println!("Keys locked");
+
+    Ok(())
+}
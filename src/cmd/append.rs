@@ -0,0 +1,127 @@
+use crate::password::Password;
+use crate::secret::{read_to_secret, SecretString};
+use crate::settings::Settings;
+use crate::sync::add_commit_update_password;
+use anyhow::{anyhow, Context};
+use git2::Repository;
+
+/// Add `lines` at the end of existing password, or lines read from stdin if there are none
+pub fn append(password_name: &str, lines: &[String], settings: &Settings) -> anyhow::Result<()> {
+    // Create empty password
+    let mut password = Password::default();
+
+    // Set path of password
+    password.set_filepath(settings.get_password_store_path()?, password_name);
+
+    // Check if password exists
+    if !password.exists() {
+        return Err(anyhow!("Password `{}` does not exist", password_name));
+    }
+
+    // Get lines before decrypting, stdin can be the output of another command
+    let input = if lines.is_empty() {
+        eprintln!(
+            "Enter lines to append to {} and press Ctrl+Z when finished:",
+            password_name
+        );
+        read_to_secret(&mut std::io::stdin()).context("Failed to read lines to append")?
+    } else {
+        let mut input = SecretString::with_capacity(lines.iter().map(|line| line.len() + 1).sum());
+        for line in lines {
+            input.push_str(line);
+            input.push('\n');
+        }
+        input
+    };
+
+    if input.is_empty() {
+        return Err(anyhow!("Nothing to append to `{}`", password_name));
+    }
+
+    // Attempt to open and decrypt password in file
+    password.open_decrypt(settings, None)?;
+
+    // Let the user know who wrote the password
+    if let Some(signature) = password.get_signature() {
+        eprintln!("Password `{}`: {}", password_name, signature);
+    }
+
+    password.append(&input)?;
+
+    // Encrypt password and write output to file
+    password.encrypt_with_key(settings)?;
+
+    // Git operations if git repo is present
+    if let Ok(repo) = Repository::open(settings.get_password_store_path()?) {
+        add_commit_update_password(&repo, &password)?
+    }
+
+    println!("Password `{}` has been updated", password_name);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::password::Password;
+    use crate::sync::{get_head_commit, get_relative_path};
+    use git2::Repository;
+    use tempfile::tempdir;
+
+    #[test]
+    fn append_lines() -> anyhow::Result<()> {
+        let mut password = Password::from_single_line("my_password");
+        password.append("recovery: 1234\n")?;
+        password.append("some notes")?;
+        assert_eq!(
+            password.contents()?,
+            "my_password\nrecovery: 1234\nsome notes\n"
+        );
+
+        // Windows line endings are kept
+        let mut password = Password::from_single_line("my_password\r\nlogin: me");
+        password.append("first\nsecond\r\n")?;
+        assert_eq!(
+            password.contents()?,
+            "my_password\r\nlogin: me\r\nfirst\r\nsecond\r\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn append_command() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let mut settings = crate::cmd::insert::tests::create_settings(&password_store);
+        settings.set_passphrase_command("echo password");
+        crate::cmd::git::init(&password_store)?;
+        let repo = Repository::open(&password_store)?;
+
+        let mut password = Password::from_multi_line(&[
+            "my_password\r\n".to_string(),
+            "login: me\r\n".to_string(),
+        ]);
+        password.set_filepath(&password_store, "password");
+        password.encrypt_with_key(&settings)?;
+
+        let lines = vec!["recovery code".to_string(), "other code".to_string()];
+        super::append("password", &lines, &settings)?;
+
+        let path_file = get_relative_path(&repo, password.get_filepath().unwrap()).unwrap();
+        let commit = String::from(get_head_commit(&repo)?.message().unwrap());
+        assert_eq!(commit, format!("Updated password {}", path_file.display()));
+
+        password.clean_password();
+        password.open_decrypt(&settings, Some("password".into()))?;
+        assert_eq!(
+            password.contents()?,
+            "my_password\r\nlogin: me\r\nrecovery code\r\nother code\r\n"
+        );
+
+        assert_eq!(super::append("other", &lines, &settings).is_err(), true);
+
+        Ok(())
+    }
+}
@@ -1,15 +1,16 @@
 use crate::password::Password;
+use crate::secret::SecretString;
 use crate::settings::Settings;
 use crate::sync::add_commit_update_password;
 use anyhow::{anyhow, Context};
 use git2::Repository;
 
-pub fn edit(password_name: &str, settings: &Settings) -> anyhow::Result<()> {
+pub fn edit(password_name: &str, ignore_policy: bool, settings: &Settings) -> anyhow::Result<()> {
     // Create empty password
     let mut password = Password::default();
 
     // Set path of password
-    password.set_filepath(settings.get_password_store_path()?, &password_name);
+    password.set_filepath(settings.get_password_store_path()?, password_name);
 
     // Check if password exists
     if !password.exists() {
@@ -17,28 +18,39 @@ pub fn edit(password_name: &str, settings: &Settings) -> anyhow::Result<()> {
     }
 
     // Attempt to open and decrypt password in file
-    let key_path = settings.get_pgp_key_path()?;
-    password.open_decrypt(key_path, None)?;
+    password.open_decrypt(settings, None)?;
+
+    // Let the user know who wrote the password
+    if let Some(signature) = password.get_signature() {
+        eprintln!("Password `{}`: {}", password_name, signature);
+    }
 
     // Open contents in text editor
-    let original_password = password.to_string()?;
-    let modified_password = edit::edit(&original_password)
-        .context(format!("Failed to edit password {}", password_name))?;
+    let original_password = password.contents()?;
+    let modified_password: SecretString = edit::edit(original_password)
+        .context(format!("Failed to edit password {}", password_name))?
+        .into();
 
     // Don't do anything if there are no changes
-    if original_password == modified_password {
+    if original_password == &*modified_password {
         println!("Password unchanged");
         return Ok(());
     }
 
+    // Only a new password has to follow the policy, not an old one whose notes are edited
+    let password_changed = original_password.lines().next() != modified_password.lines().next();
+
     // Use updated password
     password.multi_line_input(&modified_password);
+    if password_changed {
+        password.check_policy(settings, ignore_policy)?;
+    }
 
     // Encrypt password and write output to file
-    password.encrypt_with_key(settings.get_pgp_key_path()?)?;
+    password.encrypt_with_key(settings)?;
 
     // Git operations if git repo is present
-    if let Ok(repo) = Repository::open(&settings.get_password_store_path()?) {
+    if let Ok(repo) = Repository::open(settings.get_password_store_path()?) {
         add_commit_update_password(&repo, &password)?
     }
 
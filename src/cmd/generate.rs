@@ -1,16 +1,70 @@
-use crate::password::{set_to_clipboard, Password};
+use crate::generator::{Characters, Generator, Words};
+use crate::password::{passphrase_input, set_to_clipboard, Password};
+use crate::policy::Policy;
+use crate::prompt;
 use crate::settings::Settings;
-use crate::sync::add_commit_password;
+use crate::sync::{add_commit_password, add_commit_update_password};
 use anyhow::anyhow;
-use dialoguer::Confirm;
 use git2::Repository;
-use passwords::PasswordGenerator;
 
+/// Options of the generated password from the command line, they take precedence over the policy
+#[derive(Default)]
+pub struct Options {
+    pub length: Option<usize>,
+    pub no_symbols: bool,
+    pub no_digits: bool,
+    pub no_uppercase: bool,
+    pub allowed: Option<String>,
+    pub exclude: Option<String>,
+    pub min_per_class: Option<usize>,
+
+    /// Passphrase of words instead of characters
+    pub words: Option<Words>,
+
+    /// Keep the password even if it does not follow the policy
+    pub ignore_policy: bool,
+}
+
+impl Options {
+    /// Generator of the password, `policy` gives the options which are not set
+    ///
+    /// Rules of the policy are about characters, passphrases of words do not use them
+    fn generator(self, policy: Option<&Policy>) -> Generator {
+        if let Some(words) = self.words {
+            return Generator::Words(words);
+        }
+
+        let mut characters = policy.map_or_else(Characters::default, Policy::characters);
+        if let Some(length) = self.length {
+            characters.length = length;
+        }
+        characters.uppercase &= !self.no_uppercase;
+        characters.digits &= !self.no_digits;
+        characters.symbols &= !self.no_symbols;
+        if let Some(allowed) = self.allowed {
+            characters.allowed = Some(allowed);
+        }
+        if let Some(exclude) = self.exclude {
+            characters.excluded.push_str(&exclude);
+        }
+        if let Some(min_per_class) = self.min_per_class {
+            characters.min_per_class = min_per_class;
+        }
+
+        Generator::Characters(characters)
+    }
+}
+
+/// Generate password `password_name` with `options`, and the policy of its folder
+///
+/// With `in_place`, only the first line of the existing password is replaced
 pub fn generate(
     password_name: &str,
-    length: Option<usize>,
+    options: Options,
+    in_place: bool,
     force: bool,
     clipboard: bool,
+    symmetric: bool,
     settings: &Settings,
 ) -> anyhow::Result<()> {
     // Create empty password
@@ -24,17 +78,18 @@ pub fn generate(
         return Err(anyhow!("`{}` is a folder in password store", password_name));
     }
 
-    // Check if file exists, if so ask to overwrite if force flag is not passed
+    if in_place && !password.exists() {
+        return Err(anyhow!("Password `{}` does not exist", password_name));
+    }
+
+    // Check if file exists, if so ask to overwrite if force or in place flag is not passed
     if password.exists()
+        && !in_place
         && !force
-        && !Confirm::new()
-            .with_prompt(format!(
-                "An entry already exists for `{}`. Overwrite it?",
-                password_name
-            ))
-            .default(false)
-            .show_default(true)
-            .interact()?
+        && !prompt::confirm(&format!(
+            "An entry already exists for `{}`. Overwrite it?",
+            password_name
+        ))?
     {
         // If user says no
         println!("Password generation canceled.");
@@ -42,38 +97,104 @@ pub fn generate(
     }
 
     // Generate password
-    let generated = PasswordGenerator::new()
-        .length(20)
-        .spaces(true)
-        .exclude_similar_characters(true);
-    let generated = match length {
-        Some(length) => generated.length(length),
-        None => generated,
-    };
-    let output = match generated.generate_one() {
+    let ignore_policy = options.ignore_policy;
+    let generator = options.generator(password.get_policy(settings)?.as_ref());
+    let output = match generator.generate() {
         Ok(output) => output,
         Err(e) => {
             return Err(anyhow!("Password generation failed: {}", e));
         }
     };
-    password.single_line_input(&output);
+
+    if in_place {
+        // Keep other lines of the password, and its passphrase
+        password.open_decrypt(settings, None)?;
+        password.set_first_line(&output)?;
+    } else {
+        password.single_line_input(&output);
+    }
+
+    // Options from the command line can go against the policy
+    password.check_policy(settings, ignore_policy)?;
+
+    // Protect password with a passphrase instead of keys
+    if symmetric {
+        match passphrase_input(password_name) {
+            Ok(passphrase) => password.set_passphrase(&passphrase),
+            Err(e) => return Err(anyhow!("Password generation aborted: {}", e)),
+        }
+    }
 
     // Encrypt password and write output to file
-    password.encrypt_with_key(settings.get_pgp_key_path()?)?;
+    password.encrypt_with_key(settings)?;
 
     // Git operations if git repo is present
-    if let Ok(repo) = Repository::open(&settings.get_password_store_path()?) {
-        add_commit_password(&repo, &password)?
+    if let Ok(repo) = Repository::open(settings.get_password_store_path()?) {
+        if in_place {
+            add_commit_update_password(&repo, &password)?
+        } else {
+            add_commit_password(&repo, &password)?
+        }
+    }
+
+    // Let the user know how hard the password is to guess
+    if generator.is_uniform() {
+        println!("Entropy: {:.0} bits", generator.entropy());
+    } else {
+        println!("Entropy: at most {:.0} bits", generator.entropy());
     }
 
     if clipboard {
-        return set_to_clipboard(&output, &password_name);
+        return set_to_clipboard(&output, password_name);
     }
 
-    println!("Inserted `{}` in password store", password_name);
+    if in_place {
+        println!("Replaced password of `{}`", password_name);
+    } else {
+        println!("Inserted `{}` in password store", password_name);
+    }
 
     password.display_line_padding(1)?;
-    println!("{}", password.to_string()?);
+    println!("{}", &*output);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::Characters;
+    use crate::password::Password;
+    use tempfile::tempdir;
+
+    #[test]
+    fn in_place() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
+
+        let mut password = Password::from_single_line("old_password\r\nlogin: me\nsome notes\n");
+        password.set_filepath(&password_store, "website");
+        password.encrypt_with_key(&settings)?;
+
+        // Only the first line changes
+        let generated = Characters::default().generate()?;
+        password.clean_password();
+        password.open_decrypt(&settings, Some("password".into()))?;
+        password.set_first_line(&generated)?;
+        password.encrypt_with_key(&settings)?;
+
+        password.clean_password();
+        password.open_decrypt(&settings, Some("password".into()))?;
+        assert_eq!(
+            password.contents()?,
+            format!("{}\r\nlogin: me\nsome notes\n", &*generated).as_str()
+        );
+
+        let mut password = Password::from_single_line("old_password");
+        password.set_first_line("new_password")?;
+        assert_eq!(password.contents()?, "new_password\n");
+
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
+use crate::constants::{GPG_ID_FILE, GPG_ID_SIGNATURE_FILE};
 use crate::sync::{add_commit_file, init_repo};
 use git2::Repository;
 use std::path::Path;
 
 pub fn init(password_store_path: &Path) -> anyhow::Result<()> {
     // try to open repo
-    if Repository::open(&password_store_path).is_ok() {
+    if Repository::open(password_store_path).is_ok() {
         // if ok, go out
         println!(
             "Git repository is already initialized for password store {}",
@@ -14,10 +15,16 @@ pub fn init(password_store_path: &Path) -> anyhow::Result<()> {
     }
 
     // else create one
-    let repo = init_repo(&password_store_path)?;
+    let repo = init_repo(password_store_path)?;
 
     // add .gpg-id file
-    add_commit_file(&repo, &password_store_path.join(".gpg-id"))?;
+    add_commit_file(&repo, &password_store_path.join(GPG_ID_FILE))?;
+
+    // add signature of .gpg-id if there is one
+    let signature_path = password_store_path.join(GPG_ID_SIGNATURE_FILE);
+    if signature_path.is_file() {
+        add_commit_file(&repo, &signature_path)?;
+    }
 
     println!(
         "Initiated git repository for password store {}",
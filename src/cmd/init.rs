@@ -1,42 +1,101 @@
-use crate::settings::Settings;
+use crate::cmd::key::{revocation_path, write_revocation, write_secret_key};
+use crate::constants::GPG_ID_FILE;
+use crate::keyring::{load_keyring, load_keys};
+use crate::passphrase;
+use crate::password::{passphrase_input, Password};
+use crate::recipients::{
+    find_gpg_id, load_certs, resolve_recipients, sign_file, signature_path, signing_keys,
+    write_gpg_id,
+};
+use crate::settings::{KeyBackend, Settings};
+use crate::sign::signing_keypair;
+use crate::sync;
+use crate::tree::list_entries;
 use anyhow::{anyhow, Context};
 use directories_next::BaseDirs;
+use git2::Repository;
+use sequoia_openpgp::cert::CertBuilder;
+use sequoia_openpgp::crypto;
 use sequoia_openpgp::parse::Parse;
 use sequoia_openpgp::policy::StandardPolicy;
+use sequoia_openpgp::serialize::Serialize;
 use sequoia_openpgp::{Cert, Fingerprint};
 use std::fs::File;
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 /// Extract fingerprint of first private key found in file
+///
+/// A file with only public keys is accepted too, their secrets can be in gpg-agent
 fn extract_fingerprint(key: &Path) -> anyhow::Result<Fingerprint> {
     let policy = &mut StandardPolicy::new();
     let cert = Cert::from_file(key).context("Failed to load key from file")?;
 
-    // let secret_keys: Vec<Key<key::SecretParts, key::UnspecifiedRole>> = cert // <- for Rust on CLion
-    let secret_keys = cert
+    // let keys: Vec<Key<key::PublicParts, key::UnspecifiedRole>> = cert // <- for Rust on CLion
+    let mut keys = cert
         .keys()
         .with_policy(policy, None)
         .for_transport_encryption()
         .for_storage_encryption()
-        .secret()
         .alive()
         .revoked(false)
-        .into_iter()
         .map(|key| key.key().clone())
         .collect::<Vec<_>>();
 
-    let secret_key = match secret_keys.first() {
+    // Prefer keys we have the secret of
+    keys.sort_by_key(|key| !key.has_secret());
+
+    let key = match keys.first() {
         Some(key) => key,
-        None => return Err(anyhow!("Failed to find a secret key in file")),
+        None => return Err(anyhow!("Failed to find an encryption key in file")),
     };
-    Ok(secret_key.fingerprint())
+    Ok(key.fingerprint())
+}
+
+/// Generate a new key for `userid`, protected by `password`
+///
+/// The secret key is written to `secret_path`, the public key to `public_path`
+fn generate_key(
+    userid: &str,
+    password: crypto::Password,
+    secret_path: &Path,
+    public_path: &Path,
+) -> anyhow::Result<Cert> {
+    // Never overwrite an existing key
+    let revocation_path = revocation_path(secret_path);
+    for path in &[secret_path, public_path, revocation_path.as_path()] {
+        if path.exists() {
+            return Err(anyhow!("File {} already exists", path.display()));
+        }
+    }
+
+    // Primary key for certification, with subkeys for signing and encryption
+    let (cert, revocation) = CertBuilder::general_purpose(None, Some(userid))
+        .set_password(Some(password))
+        .generate()
+        .context("Failed to generate key")?;
+
+    write_secret_key(&cert, secret_path)?;
+    write_revocation(&cert, revocation, &revocation_path)?;
+
+    let mut file = File::create(public_path)
+        .with_context(|| format!("Failed to create file {}", public_path.display()))?;
+    cert.armored().serialize(&mut file)?;
+
+    println!("Generated key {} for {}", cert.fingerprint(), userid);
+    println!("Secret key written to {}", secret_path.display());
+    println!("Public key written to {}", public_path.display());
+    println!(
+        "Revocation certificate written to {}, keep it somewhere safe to revoke the key if it is lost",
+        revocation_path.display()
+    );
+
+    Ok(cert)
 }
 
 /// Get path of a potential password store based off provided path
 fn get_password_store_path(path: &Option<PathBuf>) -> anyhow::Result<PathBuf> {
     let mut new_path = match path {
-        Some(path) => dunce::canonicalize(&path)?,
+        Some(path) => dunce::canonicalize(path)?,
         None => match BaseDirs::new() {
             Some(home_dir) => PathBuf::from(home_dir.home_dir()),
             None => return Err(anyhow!("Failed to get home directory path")),
@@ -44,7 +103,7 @@ fn get_password_store_path(path: &Option<PathBuf>) -> anyhow::Result<PathBuf> {
     };
 
     // Try to detect existing password store
-    new_path.push(".gpg-id");
+    new_path.push(GPG_ID_FILE);
 
     if new_path.is_file() {
         new_path.pop();
@@ -68,17 +127,20 @@ pub fn new_password_store(pgp_key: &Path, path: Option<PathBuf>) -> anyhow::Resu
     std::fs::create_dir_all(&password_store_path)?;
 
     // Display fingerprint in hex
-    let content = format!("0x{:X}\n", fingerprint);
+    let recipient = format!("0x{:X}", fingerprint);
 
     // Construct path of `.gpg-id`
     let mut gpg_id_path = password_store_path.clone();
-    gpg_id_path.push(".gpg-id");
+    gpg_id_path.push(GPG_ID_FILE);
 
     // Write file
-    let mut file = File::create(&gpg_id_path)?;
-    file.write_all(content.as_bytes())?;
+    write_gpg_id(&gpg_id_path, &[recipient])?;
 
-    // sign `.gpg-id` with key ??
+    // Sign `.gpg-id` if signing keys are configured
+    let signing_keys = signing_keys()?;
+    if !signing_keys.is_empty() {
+        sign_file(&gpg_id_path, pgp_key, None, &signing_keys)?;
+    }
 
     println!(
         "Created new password store at location {}",
@@ -87,10 +149,256 @@ pub fn new_password_store(pgp_key: &Path, path: Option<PathBuf>) -> anyhow::Resu
     Ok(password_store_path)
 }
 
-pub fn init(pgp_key: &Path, path: Option<PathBuf>, settings: &mut Settings) -> anyhow::Result<()> {
+/// Contents of a file before it is changed, `None` if the file did not exist
+struct Original {
+    path: PathBuf,
+    contents: Option<Vec<u8>>,
+}
+
+impl Original {
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let contents = if path.is_file() {
+            Some(std::fs::read(path)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            contents,
+        })
+    }
+
+    /// Put the file back as it was
+    fn restore(self) -> anyhow::Result<()> {
+        match self.contents {
+            Some(contents) => std::fs::write(&self.path, contents)?,
+            None if self.path.is_file() => std::fs::remove_file(&self.path)?,
+            None => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Tell if one of the secret keys in the files at `paths` is protected by a password
+fn has_encrypted_key(paths: &[PathBuf]) -> anyhow::Result<bool> {
+    for path in paths {
+        for cert in load_certs(path)? {
+            if cert
+                .keys()
+                .secret()
+                .any(|key| key.key().secret().is_encrypted())
+            {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Check if `password` unlocks the signing key of the file `key`
+fn unlocks_signing_key(key: &Path, password: &str) -> anyhow::Result<bool> {
+    Ok(load_certs(key)?
+        .iter()
+        .any(|cert| signing_keypair(cert, Some(password)).is_ok()))
+}
+
+/// Write `recipients` to `gpg_id_path` and re-encrypt the passwords using this `.gpg-id`
+///
+/// Files are added to `originals` before they are changed, returns the paths of the changed files
+fn write_recipients(
+    gpg_id_path: &Path,
+    recipients: &[String],
+    settings: &Settings,
+    originals: &mut Vec<Original>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let password_store_path = settings.get_password_store_path()?;
+    let pgp_key = settings.get_pgp_key_path()?;
+    let folder = gpg_id_path
+        .parent()
+        .context("Failed to find folder of .gpg-id")?;
+
+    write_gpg_id(gpg_id_path, recipients)?;
+    println!(
+        "Recipients of {} set to {}",
+        folder.display(),
+        recipients.join(", ")
+    );
+
+    // Passwords encrypted with this `.gpg-id` (subfolders may have their own)
+    let entries = list_entries(folder)?
+        .into_iter()
+        .filter(|entry| match find_gpg_id(password_store_path, entry) {
+            Ok(path) => path == gpg_id_path,
+            Err(_) => false,
+        })
+        .collect::<Vec<_>>();
+
+    // Ask passwords of keys only once, only if keys protected by a password are used,
+    // gpg-agent takes care of its own keys
+    let signing_keys = signing_keys()?;
+    let decrypting = !entries.is_empty() && settings.get_key_backend() == KeyBackend::File;
+    let signing =
+        !signing_keys.is_empty() || (!entries.is_empty() && settings.get_sign_passwords());
+    let key_password = if decrypting && has_encrypted_key(&settings.get_key_paths()?)? {
+        Some(passphrase::key_password(settings)?)
+    } else {
+        None
+    };
+    let signing_key_password = if signing && has_encrypted_key(&[pgp_key.to_path_buf()])? {
+        // Signing key is usually one of the keys to decrypt
+        match key_password.as_ref() {
+            Some(password) if unlocks_signing_key(pgp_key, password)? => Some(password.clone()),
+            _ => Some(passphrase::signing_key_password(settings)?),
+        }
+    } else {
+        None
+    };
+
+    // Files to commit
+    let mut paths = vec![gpg_id_path.to_path_buf()];
+
+    // Sign new `.gpg-id` before using it
+    if !signing_keys.is_empty() {
+        paths.push(sign_file(
+            gpg_id_path,
+            pgp_key,
+            signing_key_password.as_deref(),
+            &signing_keys,
+        )?);
+    }
+
+    for (i, entry) in entries.iter().enumerate() {
+        // Name of password, relative to password store and without extension
+        let password_name = entry
+            .strip_prefix(password_store_path)
+            .context("Password is not inside the password store")?
+            .with_extension("");
+
+        originals.push(Original::read(entry)?);
+
+        let mut password = Password::default();
+        password.set_filepath(password_store_path, &password_name.to_string_lossy());
+        password
+            .reencrypt(settings, key_password.clone(), signing_key_password.clone())
+            .with_context(|| format!("Failed to re-encrypt `{}`", password_name.display()))?;
+
+        println!(
+            "[{}/{}] Re-encrypted `{}`",
+            i + 1,
+            entries.len(),
+            password_name.display()
+        );
+    }
+
+    paths.extend(entries);
+
+    Ok(paths)
+}
+
+/// Write `recipients` to the `.gpg-id` of `subfolder` (or of the password store)
+/// and re-encrypt the passwords using this `.gpg-id`
+///
+/// If a password can not be re-encrypted, `.gpg-id` and the passwords are put back as they were
+fn set_recipients(
+    subfolder: Option<&str>,
+    recipients: &[String],
+    settings: &Settings,
+) -> anyhow::Result<()> {
+    let password_store_path = settings.get_password_store_path()?;
+
+    // Make sure every recipient is known before changing anything
+    let own_certs = load_keys(&settings.get_key_paths()?)?;
+    resolve_recipients(recipients, &own_certs, &load_keyring(password_store_path)?)?;
+
+    let folder = match subfolder {
+        Some(subfolder) => password_store_path.join(subfolder),
+        None => password_store_path.to_path_buf(),
+    };
+    std::fs::create_dir_all(&folder)?;
+
+    let gpg_id_path = folder.join(GPG_ID_FILE);
+
+    // Files as they were, to put them back if something fails
+    let mut originals = vec![
+        Original::read(&gpg_id_path)?,
+        Original::read(&signature_path(&gpg_id_path))?,
+    ];
+
+    let paths = match write_recipients(&gpg_id_path, recipients, settings, &mut originals) {
+        Ok(paths) => paths,
+        Err(e) => {
+            for original in originals {
+                original.restore()?;
+            }
+            return Err(e);
+        }
+    };
+
+    // Git operations if git repo is present
+    if let Ok(repo) = Repository::open(password_store_path) {
+        for path in &paths {
+            let relative_path = sync::get_relative_path(&repo, path)
+                .context(format!("Failed to get relative path of {}", path.display()))?;
+            sync::add_path(&repo, &relative_path)?;
+        }
+
+        let commit_message = format!(
+            "Set recipients of {} to {}",
+            subfolder.unwrap_or("password store"),
+            recipients.join(", ")
+        );
+        sync::create_commit(&repo, &commit_message)?;
+    }
+
+    Ok(())
+}
+
+pub fn init(
+    pgp_key: &Path,
+    path: Option<PathBuf>,
+    recipients: &[String],
+    subfolder: Option<String>,
+    generate: Option<&str>,
+    public_key: Option<&Path>,
+    settings: &mut Settings,
+) -> anyhow::Result<()> {
+    if let Some(subfolder) = &subfolder {
+        if recipients.is_empty() {
+            return Err(anyhow!("Recipients are required to initiate a subfolder"));
+        }
+
+        // Stay inside the password store
+        if Path::new(subfolder)
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(anyhow!(
+                "Subfolder `{}` must be relative to the password store",
+                subfolder
+            ));
+        }
+    }
+
     // Check if a password store exists at provided location
     let mut password_store_path = get_password_store_path(&path)?;
-    password_store_path.push(".gpg-id");
+    password_store_path.push(GPG_ID_FILE);
+
+    // Generate key at the location of `pgp_key`
+    if let Some(userid) = generate {
+        let public_key = match public_key {
+            Some(public_key) => public_key.to_path_buf(),
+            None => pgp_key.with_extension("pub.asc"),
+        };
+        let password = match passphrase_input(&format!("new key of {}", userid)) {
+            Ok(password) => password,
+            Err(e) => return Err(anyhow!("Key generation aborted: {}", e)),
+        };
+
+        generate_key(userid, password.into(), pgp_key, &public_key)?;
+    }
 
     let pgp_key = dunce::canonicalize(pgp_key)?;
 
@@ -110,6 +418,11 @@ pub fn init(pgp_key: &Path, path: Option<PathBuf>, settings: &mut Settings) -> a
     settings.set_password_store_path(&password_store_path);
     settings.write()?;
 
+    // Set new recipients
+    if !recipients.is_empty() {
+        set_recipients(subfolder.as_deref(), recipients, settings)?;
+    }
+
     Ok(())
 }
 
@@ -117,6 +430,16 @@ pub fn init(pgp_key: &Path, path: Option<PathBuf>, settings: &mut Settings) -> a
 mod tests {
 
     use crate::cmd::init::new_password_store;
+    use crate::cmd::key::write_secret_key;
+    use crate::decrypt::SignatureStatus;
+    use crate::password::Password;
+    use crate::recipients::{load_certs, read_gpg_id};
+    use crate::settings::Settings;
+    use sequoia_openpgp::cert::CertBuilder;
+    use sequoia_openpgp::parse::Parse;
+    use sequoia_openpgp::policy::StandardPolicy;
+    use sequoia_openpgp::types::RevocationStatus;
+    use sequoia_openpgp::PacketPile;
     use std::fs::File;
     use std::io::Read;
     use std::path::PathBuf;
@@ -126,7 +449,7 @@ mod tests {
     fn create_password_store() -> Result<(), Box<dyn std::error::Error>> {
         let tmp_dir = tempdir()?;
 
-        let secret_key_path = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
 
         let password_store_path = new_password_store(
             secret_key_path.as_ref(),
@@ -144,4 +467,199 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn set_recipients_subfolder() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+
+        let password_store_path = new_password_store(
+            secret_key_path.as_ref(),
+            Some(PathBuf::from(tmp_dir.path())),
+        )?;
+
+        let mut settings = crate::cmd::insert::tests::create_settings(&password_store_path);
+
+        // Unknown recipient, nothing should be written
+        let recipients = vec!["nobody@example.com".to_string()];
+        let result = super::set_recipients(Some("team"), &recipients, &settings);
+        assert_eq!(result.is_err(), true);
+        assert_eq!(password_store_path.join("team").exists(), false);
+
+        // Passwords of the subfolder
+        let mut password = Password::from_single_line("my_password");
+        password.set_filepath(&password_store_path, "team/password");
+        password.encrypt_with_key(&settings)?;
+        let mut other = Password::from_single_line("other_password");
+        other.set_filepath(&password_store_path, "team/sub/other");
+        other.encrypt_with_key(&settings)?;
+        let gpg_id_path = password_store_path.join(".gpg-id");
+        let old_recipients = read_gpg_id(&gpg_id_path)?;
+
+        // Wrong password of key, nothing should be changed
+        let recipients = vec!["phil@example.com".to_string()];
+        settings.set_passphrase_command("echo wrong");
+        let result = super::set_recipients(Some("team"), &recipients, &settings);
+        assert_eq!(result.is_err(), true);
+        assert_eq!(password_store_path.join("team/.gpg-id").exists(), false);
+        assert_eq!(password.get_recipients(&[])?, old_recipients);
+
+        // Known recipient
+        settings.set_passphrase_command("echo password");
+        super::set_recipients(Some("team"), &recipients, &settings)?;
+
+        let gpg_id_path = password_store_path.join("team").join(".gpg-id");
+        assert_eq!(read_gpg_id(&gpg_id_path)?, recipients);
+
+        // Passwords are re-encrypted for the new recipients
+        for (password, contents) in &mut [(password, "my_password"), (other, "other_password")] {
+            assert_eq!(password.get_recipients(&[])?, recipients);
+            password.clean_password();
+            password.open_decrypt(&settings, None)?;
+            assert_eq!(password.contents()?, *contents);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_recipients_key_without_password() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let (cert, _) = CertBuilder::general_purpose(None, Some("new@example.com")).generate()?;
+        let key_path = tmp_dir.path().join("key.asc");
+        write_secret_key(&cert, &key_path)?;
+        assert_eq!(
+            super::has_encrypted_key(std::slice::from_ref(&key_path))?,
+            false
+        );
+
+        let password_store_path = tmp_dir.path().join("store");
+        std::fs::create_dir_all(&password_store_path)?;
+        let password_store_path = new_password_store(&key_path, Some(password_store_path))?;
+        let mut settings = Settings::default();
+        settings.set_password_store_path(&password_store_path);
+        settings.set_pgp_key_path(&key_path);
+
+        let mut password = Password::from_single_line("my_password");
+        password.set_filepath(&password_store_path, "password");
+        password.encrypt_with_key(&settings)?;
+
+        // No password is asked, there is no terminal to ask it
+        let recipients = vec!["new@example.com".to_string()];
+        super::set_recipients(None, &recipients, &settings)?;
+
+        password.clean_password();
+        password.open_decrypt(&settings, None)?;
+        assert_eq!(password.contents()?, "my_password");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reencrypt_signing_key() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let keys_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let password_store_path = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let mut settings = crate::cmd::insert::tests::create_settings(&password_store_path);
+
+        let mut password = Password::from_single_line("my_password");
+        password.set_filepath(&password_store_path, "password");
+        password.encrypt_with_key(&settings)?;
+
+        // Passwords are signed with a key which does not have the same password
+        let signing_key_path = keys_dir.path().join("signing.asc");
+        let signing_cert = super::generate_key(
+            "signing@example.com",
+            "signing".into(),
+            &signing_key_path,
+            &keys_dir.path().join("signing.pub.asc"),
+        )?;
+        settings.set_pgp_key_path(&signing_key_path);
+        settings.set_secret_keys(vec![PathBuf::from(&secret_key_path)]);
+        settings.set_sign_passwords(true);
+
+        let result =
+            password.reencrypt(&settings, Some("password".into()), Some("password".into()));
+        assert_eq!(result.is_err(), true);
+
+        password.reencrypt(&settings, Some("password".into()), Some("signing".into()))?;
+
+        password.clean_password();
+        password.open_decrypt(&settings, Some("password".into()))?;
+        assert_eq!(password.contents()?, "my_password");
+        assert_eq!(
+            matches!(
+                password.get_signature(),
+                Some(SignatureStatus::Good(fingerprint)) if *fingerprint == signing_cert.fingerprint()
+            ),
+            true
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_key() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+
+        let secret_key_path = tmp_dir.path().join("key.asc");
+        let public_key_path = tmp_dir.path().join("key.pub.asc");
+        let cert = super::generate_key(
+            "new@example.com",
+            "password".into(),
+            &secret_key_path,
+            &public_key_path,
+        )?;
+
+        // Existing key is not overwritten
+        let result = super::generate_key(
+            "new@example.com",
+            "password".into(),
+            &secret_key_path,
+            &public_key_path,
+        );
+        assert_eq!(result.is_err(), true);
+
+        // Secret key is only readable by the user
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&secret_key_path)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Revocation certificate is kept next to the secret key
+        let revocation = PacketPile::from_file(super::revocation_path(&secret_key_path))?;
+        let revoked = cert.clone().insert_packets(revocation.into_children())?;
+        assert_eq!(
+            matches!(
+                revoked.revocation_status(&StandardPolicy::new(), None),
+                RevocationStatus::Revoked(_)
+            ),
+            true
+        );
+
+        // Only the public key is exported
+        let public_keys = load_certs(&public_key_path)?;
+        assert_eq!(public_keys[0].fingerprint(), cert.fingerprint());
+        assert_eq!(public_keys[0].is_tsk(), false);
+
+        // Password store uses the encryption subkey of the new key
+        let store_path = tmp_dir.path().join("store");
+        std::fs::create_dir_all(&store_path)?;
+        let password_store_path = new_password_store(&secret_key_path, Some(store_path))?;
+        let recipients = read_gpg_id(&password_store_path.join(".gpg-id"))?;
+        assert_eq!(recipients.len(), 1);
+        assert_eq!(
+            cert.keys()
+                .any(|key| recipients[0] == format!("0x{:X}", key.key().fingerprint())),
+            true
+        );
+
+        Ok(())
+    }
 }
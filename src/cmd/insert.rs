@@ -1,15 +1,43 @@
-use crate::password::Password;
+use crate::password::{passphrase_input, Password};
+use crate::prompt;
 use crate::settings::Settings;
 use crate::sync::add_commit_password;
-use anyhow::anyhow;
-use dialoguer::Confirm;
+use anyhow::{anyhow, Context};
 use git2::Repository;
+use std::fs::File;
+use std::path::Path;
+
+/// Where the contents of a password come from
+#[derive(Clone, Copy)]
+pub enum Input<'a> {
+    /// Single line typed in the terminal
+    Line,
+
+    /// Several lines typed in the terminal
+    MultiLine,
+
+    /// File inserted as it is (keyfile, document...)
+    File(&'a Path),
+}
+
+impl<'a> Input<'a> {
+    /// Input asked by the options of the command, a single line if there are none
+    pub fn new(multi_line: bool, file: Option<&'a Path>) -> Self {
+        match file {
+            Some(file) => Input::File(file),
+            None if multi_line => Input::MultiLine,
+            None => Input::Line,
+        }
+    }
+}
 
 pub fn insert(
     password_name: &str,
-    multi_line: bool,
+    input: Input,
     echo: bool,
     force: bool,
+    symmetric: bool,
+    ignore_policy: bool,
     settings: &Settings,
 ) -> anyhow::Result<()> {
     // Create empty password
@@ -26,31 +54,50 @@ pub fn insert(
     // Check if file exists, if so ask to overwrite if force flag is not passed
     if password.exists()
         && !force
-        && !Confirm::new()
-            .with_prompt(format!(
-                "An entry already exists for {}. Overwrite it?",
-                password_name
-            ))
-            .default(false)
-            .show_default(true)
-            .interact()?
+        && !prompt::confirm(&format!(
+            "An entry already exists for {}. Overwrite it?",
+            password_name
+        ))?
     {
         // If user says no
         println!("Password insertion canceled.");
         return Ok(());
     }
 
-    // Get password from terminal
-    if let Err(e) = password.terminal_input(password_name, multi_line) {
-        return Err(anyhow!("Password insertion aborted: {}", e));
+    // Get password from terminal, unless it is a file
+    let mut file = match input {
+        Input::File(file) => Some(
+            File::open(file).with_context(|| format!("Failed to open file {}", file.display()))?,
+        ),
+        Input::Line | Input::MultiLine => {
+            let multi_line = matches!(input, Input::MultiLine);
+            if let Err(e) = password.terminal_input(password_name, multi_line) {
+                return Err(anyhow!("Password insertion aborted: {}", e));
+            }
+            println!();
+
+            // Files are not passwords, they have no policy
+            password.check_policy(settings, ignore_policy)?;
+            None
+        }
+    };
+
+    // Protect password with a passphrase instead of keys
+    if symmetric {
+        match passphrase_input(password_name) {
+            Ok(passphrase) => password.set_passphrase(&passphrase),
+            Err(e) => return Err(anyhow!("Password insertion aborted: {}", e)),
+        }
     }
-    println!();
 
-    // Encrypt password and write output to file
-    password.encrypt_with_key(settings.get_pgp_key_path()?)?;
+    // Encrypt password and write output to file, a file is encrypted as it is read
+    match &mut file {
+        Some(file) => password.encrypt_from(settings, file)?,
+        None => password.encrypt_with_key(settings)?,
+    }
 
     // Git operations if git repo is present
-    if let Ok(repo) = Repository::open(&settings.get_password_store_path()?) {
+    if let Ok(repo) = Repository::open(settings.get_password_store_path()?) {
         add_commit_password(&repo, &password)?
     }
 
@@ -59,7 +106,7 @@ pub fn insert(
     // Display password if echo flag is passed
     if echo {
         password.display_line_padding(1)?;
-        println!("{}", password.to_string()?);
+        print!("{}", password.contents()?);
     }
 
     Ok(())
@@ -69,24 +116,39 @@ pub fn insert(
 pub mod tests {
     use crate::cmd::init::new_password_store;
     use crate::password::Password;
+    use crate::settings::Settings;
     use crate::sync::{add_commit_password, get_head_commit, get_relative_path};
     use git2::Repository;
+    use sequoia_openpgp::parse::Parse;
+    use sequoia_openpgp::serialize::Serialize;
+    use sequoia_openpgp::Cert;
+    use std::fs::File;
     use std::path::{Path, PathBuf};
     use tempfile::tempdir;
 
     pub fn create_password_store(tmp_dir: &Path) -> anyhow::Result<PathBuf> {
-        let secret_key_path = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
 
         new_password_store(secret_key_path.as_ref(), Some(PathBuf::from(tmp_dir)))
     }
 
+    pub fn create_settings(password_store: &Path) -> Settings {
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+
+        let mut settings = Settings::default();
+        settings.set_password_store_path(password_store);
+        settings.set_pgp_key_path(secret_key_path.as_ref());
+
+        settings
+    }
+
     #[test]
     fn insert_single_line() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
         let password_contents = "my_super_secure_password";
         let password_name = "folder/password";
-        let password_store = create_password_store(&tmp_dir.path())?;
+        let password_store = create_password_store(tmp_dir.path())?;
         crate::cmd::git::init(&password_store)?;
 
         let mut password = Password::from_single_line(password_contents);
@@ -95,9 +157,7 @@ pub mod tests {
         // Make sure the file does not exist
         assert_eq!(password.get_filepath().unwrap().exists(), false);
 
-        password.encrypt_with_key(
-            format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR")).as_ref(),
-        )?;
+        password.encrypt_with_key(&create_settings(&password_store))?;
 
         // File should exist now
         assert_eq!(password.get_filepath().unwrap().exists(), true);
@@ -111,10 +171,11 @@ pub mod tests {
     fn insert_multi_line() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
-        let mut password_contents = Vec::new();
-        password_contents.push("multi\n".to_string());
-        password_contents.push("line\n".to_string());
-        password_contents.push("password\n".to_string());
+        let password_contents = vec![
+            "multi\n".to_string(),
+            "line\n".to_string(),
+            "password\n".to_string(),
+        ];
 
         let password_name = "folder/password";
         let password_store = create_password_store(tmp_dir.path())?;
@@ -126,9 +187,7 @@ pub mod tests {
         // Make sure the file does not exist
         assert_eq!(password.get_filepath().unwrap().exists(), false);
 
-        password.encrypt_with_key(
-            format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR")).as_ref(),
-        )?;
+        password.encrypt_with_key(&create_settings(&password_store))?;
 
         // File should exist now
         assert_eq!(password.get_filepath().unwrap().exists(), true);
@@ -138,9 +197,42 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn insert_public_key_only() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_contents = "my_super_secure_password";
+        let password_name = "password";
+        let password_store = create_password_store(tmp_dir.path())?;
+        let settings = create_settings(&password_store);
+
+        // Machine with the public key only
+        let public_key_path = tmp_dir.path().join("public-key.asc");
+        let cert = Cert::from_file(settings.get_pgp_key_path()?)?;
+        cert.armored()
+            .serialize(&mut File::create(&public_key_path)?)?;
+        let mut public_settings = Settings::default();
+        public_settings.set_password_store_path(&password_store);
+        public_settings.set_pgp_key_path(&public_key_path);
+
+        let mut password = Password::from_single_line(password_contents);
+        password.set_filepath(&password_store, password_name);
+        password.encrypt_with_key(&public_settings)?;
+
+        // Secret key is needed to decrypt
+        password.clean_password();
+        let result = password.open_decrypt(&public_settings, Some("password".into()));
+        assert_eq!(result.is_err(), true);
+
+        password.open_decrypt(&settings, Some("password".into()))?;
+        assert_eq!(password.contents()?, password_contents);
+
+        Ok(())
+    }
+
     fn git_operation(password_store_path: &Path, password: &Password) -> anyhow::Result<()> {
         let repo = Repository::open(password_store_path)?;
-        add_commit_password(&repo, &password)?;
+        add_commit_password(&repo, password)?;
 
         let commit = String::from(get_head_commit(&repo).unwrap().message().unwrap());
         let path_file = get_relative_path(&repo, password.get_filepath().unwrap()).unwrap();
@@ -150,4 +242,74 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn insert_file() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_store = create_password_store(tmp_dir.path())?;
+        let settings = create_settings(&password_store);
+        crate::cmd::git::init(&password_store)?;
+
+        // Not valid UTF-8
+        let contents = (0..=255u8).rev().cycle().take(10_000).collect::<Vec<_>>();
+        let file_path = tmp_dir.path().join("keyfile.bin");
+        std::fs::write(&file_path, &contents)?;
+
+        let mut password = Password::default();
+        password.set_filepath(&password_store, "files/keyfile");
+        password.encrypt_from(&settings, &mut File::open(&file_path)?)?;
+
+        git_operation(&password_store, &password)?;
+
+        let mut output = Vec::new();
+        password.decrypt_to(&settings, Some("password".into()), &mut output)?;
+        assert_eq!(output, contents);
+
+        // Not text
+        assert_eq!(
+            password
+                .open_decrypt(&settings, Some("password".into()))
+                .is_err(),
+            true
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn insert_symmetric() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_store = create_password_store(tmp_dir.path())?;
+        let mut settings = create_settings(&password_store);
+        settings.set_passphrase_command("echo password");
+
+        // Passphrase is not the password of the key
+        let mut password = Password::from_single_line("my_password");
+        password.set_filepath(&password_store, "break-glass");
+        password.set_passphrase("break glass");
+        password.encrypt_with_key(&settings)?;
+
+        // Known passphrase
+        let mut password = Password::default();
+        password.set_filepath(&password_store, "break-glass");
+        password.set_passphrase("break glass");
+        password.open_decrypt(&settings, None)?;
+        assert_eq!(password.contents()?, "my_password");
+
+        // Passphrase is asked, even if the password of the key is known
+        let pinentry = crate::prompt::tests::fake_pinentry(tmp_dir.path(), "break glass")?;
+        crate::prompt::use_pinentry(Some(&pinentry));
+
+        let mut password = Password::default();
+        password.set_filepath(&password_store, "break-glass");
+        let result = password.open_decrypt(&settings, Some("password".into()));
+        crate::prompt::use_pinentry(None);
+        result?;
+        assert_eq!(password.contents()?, "my_password");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,408 @@
+use crate::constants::{POLICY_FILE, SIGNING_KEY_ENV};
+use crate::keyring;
+use crate::passphrase;
+use crate::password::passphrase_input;
+use crate::recipients::{load_certs, sign_file, signing_keys};
+use crate::settings::Settings;
+use crate::sync;
+use anyhow::{anyhow, Context};
+use git2::Repository;
+use sequoia_openpgp::armor;
+use sequoia_openpgp::cert::amalgamation::ValidAmalgamation;
+use sequoia_openpgp::crypto::Password;
+use sequoia_openpgp::packet::Signature;
+use sequoia_openpgp::parse::Parse;
+use sequoia_openpgp::policy::StandardPolicy;
+use sequoia_openpgp::serialize::Serialize;
+use sequoia_openpgp::types::{KeyFlags, RevocationStatus, Timestamp};
+use sequoia_openpgp::{Cert, Packet};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Import public keys contained in file `path` to the keyring of the password store
+pub fn import(path: &Path, settings: &Settings) -> anyhow::Result<()> {
+    let password_store_path = settings.get_password_store_path()?;
+
+    let certs = load_certs(path)?;
+    let fingerprints = certs
+        .iter()
+        .map(|cert| cert.fingerprint().to_hex())
+        .collect::<Vec<_>>();
+
+    let paths = keyring::import(password_store_path, certs)?;
+
+    for fingerprint in &fingerprints {
+        println!("Imported public key {}", fingerprint);
+    }
+
+    // Git operations if git repo is present
+    if let Ok(repo) = Repository::open(password_store_path) {
+        for path in &paths {
+            let relative_path = sync::get_relative_path(&repo, path)
+                .context(format!("Failed to get relative path of {}", path.display()))?;
+            sync::add_path(&repo, &relative_path)?;
+        }
+
+        let commit_message = format!("Imported public keys {}", fingerprints.join(", "));
+        sync::create_commit(&repo, &commit_message)?;
+    }
+
+    Ok(())
+}
+
+/// List public keys of the keyring of the password store
+pub fn list(settings: &Settings) -> anyhow::Result<()> {
+    let certs = keyring::load_keyring(settings.get_password_store_path()?)?;
+
+    if certs.is_empty() {
+        println!("There are no public keys in the password store");
+        return Ok(());
+    }
+
+    for cert in certs {
+        println!("{}", cert.fingerprint());
+        for uid in cert.userids() {
+            println!("    {}", String::from_utf8_lossy(uid.userid().value()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove public key `key` (fingerprint, key id or email) from the keyring of the password store
+pub fn remove(key: &str, settings: &Settings) -> anyhow::Result<()> {
+    let password_store_path = settings.get_password_store_path()?;
+
+    let cert = keyring::find(password_store_path, key)?;
+    let path = keyring::remove(password_store_path, &cert)?;
+
+    println!("Removed public key {}", cert.fingerprint());
+
+    // Git operations if git repo is present
+    if let Ok(repo) = Repository::open(password_store_path) {
+        let relative_path = sync::get_relative_path(&repo, &path)
+            .context(format!("Failed to get relative path of {}", path.display()))?;
+        sync::rm_file(&repo, &relative_path)?;
+
+        let commit_message = format!("Removed public key {}", cert.fingerprint().to_hex());
+        sync::create_commit(&repo, &commit_message)?;
+    }
+
+    Ok(())
+}
+
+/// Format `time` like `2021-01-31T12:00:00Z`
+fn format_time(time: SystemTime) -> String {
+    match Timestamp::try_from(time) {
+        Ok(timestamp) => timestamp.to_string(),
+        Err(_) => format!("{:?}", time),
+    }
+}
+
+/// Format `flags` like gpg does, for example `[SC]`
+fn format_flags(flags: Option<KeyFlags>) -> String {
+    let flags = match flags {
+        Some(flags) => flags,
+        None => return "[]".into(),
+    };
+
+    let mut s = String::from("[");
+    if flags.for_signing() {
+        s.push('S');
+    }
+    if flags.for_certification() {
+        s.push('C');
+    }
+    if flags.for_transport_encryption() || flags.for_storage_encryption() {
+        s.push('E');
+    }
+    if flags.for_authentication() {
+        s.push('A');
+    }
+    s.push(']');
+
+    s
+}
+
+fn format_revocation(status: RevocationStatus) -> &'static str {
+    match status {
+        RevocationStatus::Revoked(_) => "revoked",
+        RevocationStatus::CouldBe(_) => "possibly revoked",
+        RevocationStatus::NotAsFarAsWeKnow => "not revoked",
+    }
+}
+
+/// Show fingerprint, user ids and subkeys of the key of the password store
+pub fn info(settings: &Settings) -> anyhow::Result<()> {
+    let policy = &StandardPolicy::new();
+    let cert =
+        Cert::from_file(settings.get_pgp_key_path()?).context("Failed to load key from file")?;
+    let valid_cert = cert.with_policy(policy, None).context("Key is not valid")?;
+
+    println!("{}", cert.fingerprint());
+    for uid in valid_cert.userids() {
+        println!("    {}", String::from_utf8_lossy(uid.userid().value()));
+    }
+    println!(
+        "Revocation: {}",
+        format_revocation(valid_cert.revocation_status())
+    );
+    println!("Keys:");
+
+    for key in valid_cert.keys() {
+        let expiration = match key.key_expiration_time() {
+            Some(time) => format_time(time),
+            None => "never".into(),
+        };
+        let state = match key.alive() {
+            Ok(()) => format_revocation(key.revocation_status()),
+            Err(_) => "expired",
+        };
+        let secret = if !key.key().has_secret() {
+            "public only"
+        } else if key.key().has_unencrypted_secret() {
+            "secret, no password"
+        } else {
+            "secret"
+        };
+
+        println!(
+            "    {} {} created {}, expires {}, {}, {}",
+            key.key().fingerprint(),
+            format_flags(key.key_flags()),
+            format_time(key.key().creation_time()),
+            expiration,
+            state,
+            secret
+        );
+    }
+
+    Ok(())
+}
+
+/// Write the armored public key of the password store to `path`, or to stdout
+pub fn export_public(path: Option<&Path>, settings: &Settings) -> anyhow::Result<()> {
+    let cert =
+        Cert::from_file(settings.get_pgp_key_path()?).context("Failed to load key from file")?;
+
+    // Secret key material is never serialized with a `Cert`
+    match path {
+        Some(path) => {
+            let mut file = File::create(path)
+                .with_context(|| format!("Failed to create file {}", path.display()))?;
+            cert.armored().serialize(&mut file)?;
+            println!(
+                "Exported public key {} to {}",
+                cert.fingerprint(),
+                path.display()
+            );
+        }
+        None => cert.armored().serialize(&mut std::io::stdout())?,
+    }
+
+    Ok(())
+}
+
+/// Create the new file `path`, only readable by the user
+///
+/// Fails if the file already exists
+fn create_private_file(path: &Path) -> anyhow::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    options
+        .open(path)
+        .with_context(|| format!("Failed to create file {}", path.display()))
+}
+
+/// Write `cert` with its secret keys to the armored file `path`, which must not exist
+pub fn write_secret_key(cert: &Cert, path: &Path) -> anyhow::Result<()> {
+    let mut file = create_private_file(path)?;
+    let mut writer = armor::Writer::new(&mut file, armor::Kind::SecretKey)?;
+    cert.as_tsk().serialize(&mut writer)?;
+    writer.finalize()?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Path of the revocation certificate of the secret key file `path`, like `key.asc.rev`
+pub fn revocation_path(path: &Path) -> PathBuf {
+    let mut revocation_path = path.as_os_str().to_owned();
+    revocation_path.push(".rev");
+
+    PathBuf::from(revocation_path)
+}
+
+/// Write the revocation certificate `revocation` of `cert` to the armored file `path`,
+/// which must not exist
+///
+/// Anyone who has it can revoke the key, so it is only readable by the user
+pub fn write_revocation(cert: &Cert, revocation: Signature, path: &Path) -> anyhow::Result<()> {
+    let comment = format!("Revocation certificate for {}", cert.fingerprint());
+
+    let mut file = create_private_file(path)?;
+    let mut writer = armor::Writer::with_headers(
+        &mut file,
+        armor::Kind::PublicKey,
+        vec![("Comment", comment.as_str())],
+    )?;
+    Packet::from(revocation).serialize(&mut writer)?;
+    writer.finalize()?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Protect secret keys of `cert` with `new_password` instead of `old_password`
+fn change_password(
+    cert: Cert,
+    old_password: &Password,
+    new_password: &Password,
+) -> anyhow::Result<Cert> {
+    let mut packets: Vec<Packet> = Vec::new();
+
+    for key in cert.keys().secret() {
+        let key = key.key().clone();
+        let key = if key.secret().is_encrypted() {
+            key.decrypt_secret(old_password)
+                .map_err(|_| anyhow!("Invalid password for key"))?
+        } else {
+            key
+        };
+        let key = key.encrypt_secret(new_password)?;
+
+        packets.push(if key.fingerprint() == cert.fingerprint() {
+            key.role_into_primary().into()
+        } else {
+            key.role_into_subordinate().into()
+        });
+    }
+
+    // Inserted keys replace the secret key material of the existing ones
+    cert.insert_packets(packets)
+}
+
+/// Change the password protecting the secret key file of the password store
+pub fn passwd(settings: &Settings) -> anyhow::Result<()> {
+    let key_path = settings.get_pgp_key_path()?;
+    let cert = Cert::from_file(key_path).context("Failed to load key from file")?;
+    if !cert.is_tsk() {
+        return Err(anyhow!("There is no secret key in {}", key_path.display()));
+    }
+    let fingerprint = cert.fingerprint();
+
+    let old_password: Password = passphrase::key_password(settings)?.into();
+    let new_password: Password = match passphrase_input(&format!("key {}", fingerprint)) {
+        Ok(password) => password.into(),
+        Err(e) => return Err(anyhow!("Password change aborted: {}", e)),
+    };
+
+    let cert = change_password(cert, &old_password, &new_password)?;
+
+    // Write to a new file first to never lose the key, a file left by
+    // an interrupted change is not overwritten
+    let mut new_path = key_path.as_os_str().to_owned();
+    new_path.push(".new");
+    write_secret_key(&cert, new_path.as_ref())?;
+
+    // Key file keeps its permissions
+    std::fs::set_permissions(&new_path, std::fs::metadata(key_path)?.permissions())?;
+    std::fs::rename(&new_path, key_path)?;
+
+    println!("Password of key {} has been changed", fingerprint);
+
+    Ok(())
+}
+
+/// Sign the `.pass-policy` file of the password store, or of its `subfolder`, with the key of the
+/// password store
+pub fn sign_policy(subfolder: Option<&str>, settings: &Settings) -> anyhow::Result<()> {
+    let password_store_path = settings.get_password_store_path()?;
+    let policy_path = match subfolder {
+        Some(subfolder) => password_store_path.join(subfolder),
+        None => password_store_path.to_path_buf(),
+    }
+    .join(POLICY_FILE);
+
+    if !policy_path.is_file() {
+        return Err(anyhow!("There is no policy file {}", policy_path.display()));
+    }
+
+    let signing_keys = signing_keys()?;
+    if signing_keys.is_empty() {
+        return Err(anyhow!(
+            "There are no keys allowed to sign policies, set them in {}",
+            SIGNING_KEY_ENV
+        ));
+    }
+
+    let password = passphrase::signing_key_password(settings)?;
+    let signature_path = sign_file(
+        &policy_path,
+        settings.get_pgp_key_path()?,
+        Some(&password),
+        &signing_keys,
+    )?;
+
+    println!("Signed policy {}", policy_path.display());
+
+    // Git operations if git repo is present
+    if let Ok(repo) = Repository::open(password_store_path) {
+        let mut relative_paths = Vec::new();
+        for path in &[&policy_path, &signature_path] {
+            let relative_path = sync::get_relative_path(&repo, path)
+                .context(format!("Failed to get relative path of {}", path.display()))?;
+            sync::add_path(&repo, &relative_path)?;
+            relative_paths.push(relative_path);
+        }
+
+        let commit_message = format!("Signed policy {}", relative_paths[0].display());
+        sync::create_commit(&repo, &commit_message)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decrypt::unlock_key;
+    use sequoia_openpgp::crypto::Password;
+    use sequoia_openpgp::parse::Parse;
+    use sequoia_openpgp::policy::StandardPolicy;
+    use sequoia_openpgp::Cert;
+
+    #[test]
+    fn change_password() -> anyhow::Result<()> {
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let cert = Cert::from_file(&secret_key_path)?;
+
+        // Wrong current password
+        let result = super::change_password(cert.clone(), &"wrong".into(), &"new".into());
+        assert_eq!(result.is_err(), true);
+
+        let cert = super::change_password(cert, &Password::from("password"), &"new".into())?;
+        assert_eq!(cert.is_tsk(), true);
+
+        let policy = &StandardPolicy::new();
+        for key in cert.keys().with_policy(policy, None).secret() {
+            assert_eq!(
+                unlock_key(key.key().clone(), Some("password"), "test").is_err(),
+                true
+            );
+            assert_eq!(
+                unlock_key(key.key().clone(), Some("new"), "test").is_ok(),
+                true
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,47 +1,99 @@
+use crate::keyring::load_known_certs;
+use crate::password::Password;
+use crate::prompt;
+use crate::recipients::{find_gpg_id, read_gpg_id, read_verified_gpg_id};
 use crate::settings::Settings;
 use crate::sync;
-use anyhow::anyhow;
-use dialoguer::Confirm;
+use crate::tree::list_entries;
+use anyhow::{anyhow, Context};
 use git2::Repository;
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 
-fn move_action(old: &PathBuf, new: &PathBuf, force: bool, new_path: &str) -> anyhow::Result<()> {
+/// Move `old` to `new`
+///
+/// Returns `false` if the user canceled the operation
+fn move_action(old: &PathBuf, new: &PathBuf, force: bool, new_path: &str) -> anyhow::Result<bool> {
     // If `new_path` exists
     if new.exists() {
         // If it's a folder, move `old_path` inside folder `new_path`
         if new.is_dir() {
             // Construct list of paths to move
-            let mut from_paths = Vec::new();
-            from_paths.push(&old);
+            let from_paths = vec![old];
 
             let options = fs_extra::dir::CopyOptions::new();
-            fs_extra::move_items(&from_paths, &new, &options)?;
+            fs_extra::move_items(&from_paths, new, &options)?;
         } else {
             // (It's a file)
 
             // File already exists and will be overwritten
             if !force
-                && !Confirm::new()
-                    .with_prompt(format!(
-                        "The entry {} already exists. Overwrite it?",
-                        new_path
-                    ))
-                    .default(false)
-                    .show_default(true)
-                    .interact()?
+                && !prompt::confirm(&format!(
+                    "The entry {} already exists. Overwrite it?",
+                    new_path
+                ))?
             {
                 // If user says no
                 println!("Moving operation canceled");
-                return Ok(());
+                return Ok(false);
             }
 
             // Rename old_path to new_path, overwrite file at new_path
-            std::fs::rename(&old, &new)?;
+            std::fs::rename(old, new)?;
         }
     } else {
         // Rename old_path to new_path
-        std::fs::rename(&old, &new)?;
+        std::fs::rename(old, new)?;
     }
+    Ok(true)
+}
+
+/// Re-encrypt moved passwords whose recipients are not the same at their new location
+///
+/// `entries` contains the new path of each password, along with its previous recipients.
+/// On error, passwords already re-encrypted are restored as they were
+fn reencrypt_moved(
+    entries: &[(PathBuf, Vec<String>)],
+    store_path: &Path,
+    settings: &Settings,
+) -> anyhow::Result<()> {
+    // Encrypted files before their re-encryption
+    let mut originals = Vec::new();
+
+    // Keys allowed to sign `.gpg-id` files
+    let certs = load_known_certs(store_path, &settings.get_key_paths()?)?;
+
+    for (path, old_recipients) in entries {
+        let new_recipients = read_verified_gpg_id(&find_gpg_id(store_path, path)?, &certs)?;
+        if &new_recipients == old_recipients {
+            continue;
+        }
+
+        // Name of password, relative to password store and without extension
+        let password_name = path
+            .strip_prefix(store_path)
+            .context("Moved password is not inside the password store")?
+            .with_extension("");
+
+        let mut password = Password::default();
+        password.set_filepath(store_path, &password_name.to_string_lossy());
+
+        let original = std::fs::read(path)?;
+        if let Err(e) = password.reencrypt(settings, None, None) {
+            for (path, original) in originals {
+                std::fs::write(path, original)?;
+            }
+            return Err(e);
+        }
+        originals.push((path, original));
+
+        println!(
+            "Re-encrypted `{}` for {}",
+            password_name.display(),
+            new_recipients.join(", ")
+        );
+    }
+
     Ok(())
 }
 
@@ -51,8 +103,9 @@ pub fn m0ve(
     force: bool,
     settings: &Settings,
 ) -> anyhow::Result<()> {
-    let mut old = settings.get_password_store_path()?.join(old_path);
-    let new = {
+    let store_path = settings.get_password_store_path()?;
+    let mut old = store_path.join(old_path);
+    let mut new = {
         let p = settings.get_password_store_path()?;
 
         // To be able to move stuff to the root of the password store
@@ -66,19 +119,62 @@ pub fn m0ve(
 
     // If old path does not exist, try with .gpg at the end
     if !old.exists() {
-        old.set_extension("gpg");
+        let mut path = old.into_os_string();
+        path.push(".gpg");
+        old = PathBuf::from(path);
+
         // If it still does not exist, then give up
         if !old.exists() {
             return Err(anyhow!("Could not locate {} in password store", old_path));
         }
+
+        // Moving a password file, keep the extension
+        if !new.is_dir() && new.extension() != Some(OsStr::new("gpg")) {
+            let mut path = new.into_os_string();
+            path.push(".gpg");
+            new = PathBuf::from(path);
+        }
+    }
+
+    // Final location of `old`
+    let destination = if new.is_dir() {
+        new.join(
+            old.file_name()
+                .context("Failed to get name of path to move")?,
+        )
+    } else {
+        new.clone()
+    };
+
+    // Remember recipients of the passwords before moving them
+    let mut moved_entries = Vec::new();
+    for entry in list_entries(&old)? {
+        let recipients = read_gpg_id(&find_gpg_id(store_path, &entry)?)?;
+        let relative_path = entry.strip_prefix(&old)?;
+        let new_entry = if relative_path.as_os_str().is_empty() {
+            destination.clone()
+        } else {
+            destination.join(relative_path)
+        };
+        moved_entries.push((new_entry, recipients));
+    }
+
+    if !move_action(&old, &new, force, new_path)? {
+        return Ok(());
     }
 
-    move_action(&old, &new, force, new_path)?;
+    // Same behavior as pass, passwords must be readable by the recipients of their new location
+    if let Err(e) = reencrypt_moved(&moved_entries, store_path, settings) {
+        // Move back what was moved
+        std::fs::rename(&destination, &old)
+            .with_context(|| format!("Failed to move `{}` back to `{}`", new_path, old_path))?;
+        return Err(e);
+    }
 
     println!("Moved `{}` to `{}`", old_path, new_path);
 
     // Git operations if git repo is present
-    if let Ok(repo) = Repository::open(&settings.get_password_store_path()?) {
+    if let Ok(repo) = Repository::open(settings.get_password_store_path()?) {
         sync::rm_file(&repo, &old)?;
         sync::add_path(&repo, &new)?;
         let commit_message = format!("Moved {} to {}", old_path, new_path);
@@ -91,21 +187,23 @@ pub fn m0ve(
 #[cfg(test)]
 mod tests {
     use crate::password::Password;
+    use std::fs::File;
+    use std::io::Write;
     use tempfile::tempdir;
 
     #[test]
     fn move_file_to_folder() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
-        let pgp_key = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
         let password_contents = "my_super_secure_password";
         let password_name = "password";
-        let password_store = crate::cmd::insert::tests::create_password_store(&tmp_dir.path())?;
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
 
         // create password
         let mut password = Password::from_single_line(password_contents);
         password.set_filepath(&password_store, password_name);
-        password.encrypt_with_key(pgp_key.as_ref())?;
+        password.encrypt_with_key(&settings)?;
 
         // make sure the password exists
         assert_eq!(password.exists(), true);
@@ -133,16 +231,16 @@ mod tests {
     fn move_folder_to_folder() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
-        let pgp_key = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
         let password_contents = "my_super_secure_password";
         let folder_name = "folder";
         let password_name = format!("{}/password", folder_name);
-        let password_store = crate::cmd::insert::tests::create_password_store(&tmp_dir.path())?;
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
 
         // create password
         let mut password = Password::from_single_line(password_contents);
         password.set_filepath(&password_store, &password_name);
-        password.encrypt_with_key(pgp_key.as_ref())?;
+        password.encrypt_with_key(&settings)?;
 
         // make sure the password exists
         assert_eq!(password.exists(), true);
@@ -170,15 +268,15 @@ mod tests {
     fn rename_file() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
-        let pgp_key = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
         let password_contents = "my_super_secure_password";
         let password_name = "password";
-        let password_store = crate::cmd::insert::tests::create_password_store(&tmp_dir.path())?;
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
 
         // create password
         let mut password = Password::from_single_line(password_contents);
         password.set_filepath(&password_store, password_name);
-        password.encrypt_with_key(pgp_key.as_ref())?;
+        password.encrypt_with_key(&settings)?;
 
         // make sure the password exists
         assert_eq!(password.exists(), true);
@@ -204,16 +302,16 @@ mod tests {
     fn rename_folder() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
-        let pgp_key = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
         let password_contents = "my_super_secure_password";
         let folder_name = "folder";
         let password_name = format!("{}/password", folder_name);
-        let password_store = crate::cmd::insert::tests::create_password_store(&tmp_dir.path())?;
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
 
         // create password
         let mut password = Password::from_single_line(password_contents);
         password.set_filepath(&password_store, &password_name);
-        password.encrypt_with_key(pgp_key.as_ref())?;
+        password.encrypt_with_key(&settings)?;
 
         // make sure the password exists
         assert_eq!(password.exists(), true);
@@ -233,4 +331,91 @@ mod tests {
         assert_eq!(folder_path.is_dir(), true);
         Ok(())
     }
+
+    #[test]
+    fn move_file_to_other_recipients() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_contents = "my_super_secure_password";
+        let password_name = "password";
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
+
+        // create password
+        let mut password = Password::from_single_line(password_contents);
+        password.set_filepath(&password_store, password_name);
+        password.encrypt_with_key(&settings)?;
+
+        // create folder with its own recipients
+        let folder = "team";
+        let folder_path = password_store.join(folder);
+        std::fs::create_dir(&folder_path)?;
+        File::create(folder_path.join(".gpg-id"))?.write_all(b"phil@example.com\n")?;
+
+        // do the action
+        let old = password_store.join(format!("{}.gpg", password_name));
+        let entries = crate::tree::list_entries(&old)?;
+        assert_eq!(entries, vec![old.clone()]);
+        super::move_action(&old, &folder_path, true, folder)?;
+
+        // recipients of new location are different
+        let new_password_name = format!("{}/{}", folder, password_name);
+        let mut password = Password::default();
+        password.set_filepath(&password_store, &new_password_name);
+        assert_eq!(
+            password.get_recipients(&[])?,
+            vec!["phil@example.com".to_string()]
+        );
+
+        // re-encrypt and make sure the content is still the same
+        password.reencrypt(&settings, Some("password".into()), None)?;
+        password.clean_password();
+        password.open_decrypt(&settings, Some("password".into()))?;
+        assert_eq!(password.contents()?, password_contents);
+
+        Ok(())
+    }
+
+    #[test]
+    fn move_dotted_entry() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_contents = "my_super_secure_password";
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let mut settings = crate::cmd::insert::tests::create_settings(&password_store);
+        settings.set_passphrase_command("echo password");
+
+        // create password named like a website
+        let mut password = Password::from_single_line(password_contents);
+        password.set_filepath(&password_store, "example.com");
+        password.encrypt_with_key(&settings)?;
+
+        // create folder with its own recipients
+        let folder_path = password_store.join("team");
+        std::fs::create_dir(&folder_path)?;
+        File::create(folder_path.join(".gpg-id"))?.write_all(b"phil@example.com\n")?;
+
+        super::m0ve("example.com", "team/example.org", false, &settings)?;
+        assert_eq!(password_store.join("example.com.gpg").exists(), false);
+
+        let mut password = Password::default();
+        password.set_filepath(&password_store, "team/example.org");
+        password.open_decrypt(&settings, None)?;
+        assert_eq!(password.contents()?, password_contents);
+
+        // password is moved back when it can not be re-encrypted
+        let other_path = password_store.join("other");
+        std::fs::create_dir(&other_path)?;
+        File::create(other_path.join(".gpg-id"))?.write_all(b"nobody@example.com\n")?;
+
+        let result = super::m0ve("team/example.org", "other", false, &settings);
+        assert_eq!(result.is_err(), true);
+        assert_eq!(other_path.join("example.org.gpg").exists(), false);
+
+        password.clean_password();
+        password.open_decrypt(&settings, None)?;
+        assert_eq!(password.contents()?, password_contents);
+
+        Ok(())
+    }
 }
@@ -1,14 +1,19 @@
+pub mod agent;
+pub mod append;
 pub mod edit;
 pub mod generate;
 pub mod git;
 pub mod init;
 pub mod insert;
+pub mod key;
 pub mod list;
 pub mod m0ve;
 pub mod otp;
 pub mod remove;
+pub mod set;
 pub mod show;
 
+pub use self::append::append;
 pub use self::edit::edit;
 pub use self::generate::generate;
 pub use self::init::init;
@@ -16,4 +21,5 @@ pub use self::insert::insert;
 pub use self::list::list;
 pub use self::m0ve::m0ve;
 pub use self::remove::remove;
+pub use self::set::{set, unset};
 pub use self::show::show;
@@ -17,7 +17,7 @@ fn extract_secret_key(password: &Password) -> anyhow::Result<Vec<u8>> {
         .filter(|(key, _)| key == "secret")
         .map(|(_, value)| value)
         .collect::<Vec<_>>()
-        .get(0)
+        .first()
         .context("Failed to get secret key")?
         .to_string();
 
@@ -40,7 +40,7 @@ pub fn code(password_name: &str, clipboard: bool, settings: &Settings) -> anyhow
     let mut password = Password::default();
 
     // Set path of password
-    password.set_filepath(settings.get_password_store_path()?, &password_name);
+    password.set_filepath(settings.get_password_store_path()?, password_name);
 
     // Check if password exists
     if !password.exists() {
@@ -48,8 +48,7 @@ pub fn code(password_name: &str, clipboard: bool, settings: &Settings) -> anyhow
     }
 
     // Attempt to open and decrypt password in file
-    let key_path = settings.get_pgp_key_path()?;
-    password.open_decrypt(key_path, None)?;
+    password.open_decrypt(settings, None)?;
 
     // Get OTP secret key
     let secret_key = extract_secret_key(&password)?;
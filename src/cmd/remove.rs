@@ -61,10 +61,10 @@ pub fn remove(path: &str, settings: &Settings) -> anyhow::Result<()> {
 
     let base_path = settings.get_password_store_path()?;
     let orig_to_rm = current_path.clone();
-    remove_file(&mut current_path, base_path, &path)?;
+    remove_file(&mut current_path, base_path, path)?;
 
     // Git operations if git repo is present
-    if let Ok(repo) = Repository::open(&settings.get_password_store_path()?) {
+    if let Ok(repo) = Repository::open(settings.get_password_store_path()?) {
         let relative_path = sync::get_relative_path(&repo, &orig_to_rm)
             .context(format!("Failed to get relative path of `{}`", path))?;
         sync::rm_file(&repo, &relative_path)?;
@@ -85,15 +85,15 @@ mod tests {
     fn remove_file() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
-        let pgp_key = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
         let password_contents = "my_super_secure_password";
         let password_name = "fol1/fol2/password";
-        let password_store = crate::cmd::insert::tests::create_password_store(&tmp_dir.path())?;
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
 
         // create password
         let mut password = Password::from_single_line(password_contents);
         password.set_filepath(&password_store, password_name);
-        password.encrypt_with_key(pgp_key.as_ref())?;
+        password.encrypt_with_key(&settings)?;
 
         // make sure the password exists
         assert_eq!(password.exists(), true);
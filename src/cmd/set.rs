@@ -0,0 +1,179 @@
+use crate::password::Password;
+use crate::secret::SecretString;
+use crate::settings::Settings;
+use crate::sync::add_commit_fields;
+use anyhow::anyhow;
+use git2::Repository;
+
+/// Open and decrypt existing password `password_name`
+fn open(password_name: &str, settings: &Settings) -> anyhow::Result<Password> {
+    let mut password = Password::default();
+    password.set_filepath(settings.get_password_store_path()?, password_name);
+
+    if !password.exists() {
+        return Err(anyhow!("Password `{}` does not exist", password_name));
+    }
+
+    password.open_decrypt(settings, None)?;
+
+    // Let the user know who wrote the password
+    if let Some(signature) = password.get_signature() {
+        eprintln!("Password `{}`: {}", password_name, signature);
+    }
+
+    Ok(password)
+}
+
+/// Encrypt password and commit the change of the fields `names`
+fn save(
+    password: &Password,
+    action: &str,
+    names: &[&str],
+    settings: &Settings,
+) -> anyhow::Result<()> {
+    password.encrypt_with_key(settings)?;
+
+    // Git operations if git repo is present
+    if let Ok(repo) = Repository::open(settings.get_password_store_path()?) {
+        add_commit_fields(&repo, password, action, names)?
+    }
+
+    Ok(())
+}
+
+/// Set fields of password, `fields` are `name=value`
+pub fn set(password_name: &str, fields: &[String], settings: &Settings) -> anyhow::Result<()> {
+    let fields = fields
+        .iter()
+        .map(|field| match field.find('=') {
+            Some(separator) => Ok((&field[..separator], &field[separator + 1..])),
+            None => Err(anyhow!("Field `{}` is not like `name=value`", field)),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut password = open(password_name, settings)?;
+    let original_password = SecretString::from(password.contents()?);
+
+    for (name, value) in &fields {
+        password.set_field(name, value)?;
+    }
+
+    // Don't do anything if there are no changes
+    if *original_password == *password.contents()? {
+        println!("Password unchanged");
+        return Ok(());
+    }
+
+    let names = fields.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    save(&password, "Updated", &names, settings)?;
+
+    println!("Password `{}` has been updated", password_name);
+
+    Ok(())
+}
+
+/// Remove fields `names` of password
+pub fn unset(password_name: &str, names: &[String], settings: &Settings) -> anyhow::Result<()> {
+    let mut password = open(password_name, settings)?;
+
+    for name in names {
+        if !password.unset_field(name)? {
+            return Err(anyhow!(
+                "Password `{}` has no field `{}`",
+                password_name,
+                name
+            ));
+        }
+    }
+
+    let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+    save(&password, "Removed", &names, settings)?;
+
+    println!("Password `{}` has been updated", password_name);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::password::Password;
+    use crate::sync::{get_head_commit, get_relative_path};
+    use git2::Repository;
+    use tempfile::tempdir;
+
+    #[test]
+    fn set_unset_fields() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let mut settings = crate::cmd::insert::tests::create_settings(&password_store);
+        settings.set_passphrase_command("echo password");
+        crate::cmd::git::init(&password_store)?;
+        let repo = Repository::open(&password_store)?;
+
+        let mut password = Password::from_multi_line(&[
+            "my_password\n".to_string(),
+            "login: me\n".to_string(),
+            "some notes\n".to_string(),
+        ]);
+        password.set_filepath(&password_store, "password");
+        password.encrypt_with_key(&settings)?;
+        let path_file = get_relative_path(&repo, password.get_filepath().unwrap()).unwrap();
+
+        let fields = |fields: &[&str]| fields.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let contents = |password: &mut Password| -> anyhow::Result<String> {
+            password.clean_password();
+            password.open_decrypt(&settings, Some("password".into()))?;
+            Ok(password.contents()?.to_string())
+        };
+
+        // Values can contain `=`, they are not in the commit message
+        super::set(
+            "password",
+            &fields(&["login=you", "url=https://example.com/?a=b"]),
+            &settings,
+        )?;
+        let commit = String::from(get_head_commit(&repo)?.message().unwrap());
+        assert_eq!(
+            commit,
+            format!("Updated fields login, url of {}", path_file.display())
+        );
+        assert_eq!(
+            contents(&mut password)?,
+            "my_password\nlogin: you\nsome notes\nurl: https://example.com/?a=b\n"
+        );
+
+        // Nothing is committed without changes
+        let head = get_head_commit(&repo)?.id();
+        super::set("password", &fields(&["login=you"]), &settings)?;
+        assert_eq!(get_head_commit(&repo)?.id(), head);
+
+        assert_eq!(
+            super::set("password", &fields(&["login"]), &settings).is_err(),
+            true
+        );
+        assert_eq!(
+            super::set("other", &fields(&["login=you"]), &settings).is_err(),
+            true
+        );
+
+        super::unset("password", &fields(&["login"]), &settings)?;
+        let commit = String::from(get_head_commit(&repo)?.message().unwrap());
+        assert_eq!(
+            commit,
+            format!("Removed field login of {}", path_file.display())
+        );
+        assert_eq!(
+            contents(&mut password)?,
+            "my_password\nsome notes\nurl: https://example.com/?a=b\n"
+        );
+
+        assert_eq!(
+            super::unset("password", &fields(&["login"]), &settings).is_err(),
+            true
+        );
+        assert_eq!(get_head_commit(&repo)?.message().unwrap(), commit);
+
+        Ok(())
+    }
+}
@@ -1,19 +1,50 @@
-use crate::password::{set_to_clipboard, Password};
+use crate::password::{set_to_clipboard, write_new_file, Password};
 use crate::settings::Settings;
 use anyhow::{anyhow, Context};
 use qr2term::print_qr;
+use std::path::Path;
+
+/// Part of a password to show
+#[derive(Clone, Copy)]
+pub enum Part<'a> {
+    /// Whole password
+    All,
+
+    /// Specific line
+    Line(usize),
+
+    /// Value of a field, like `login`
+    Field(&'a str),
+
+    /// Names of the fields
+    FieldNames,
+}
+
+impl<'a> Part<'a> {
+    /// Part asked by the options of the command, the whole password if there are none
+    pub fn new(line: Option<usize>, field: Option<&'a str>, field_names: bool) -> Self {
+        match (line, field) {
+            _ if field_names => Part::FieldNames,
+            (Some(line), _) => Part::Line(line),
+            (None, Some(field)) => Part::Field(field),
+            (None, None) => Part::All,
+        }
+    }
+}
 
 pub fn show(
     password_name: Option<String>,
-    line: Option<usize>,
+    part: Part,
     clipboard: bool,
     qr_code: bool,
+    output: Option<&Path>,
+    force: bool,
     settings: &Settings,
 ) -> anyhow::Result<()> {
     // Check whether to run the `list` command or not
     match &password_name {
         Some(password) => {
-            let path = settings.get_password_store_path()?.join(&password);
+            let path = settings.get_password_store_path()?.join(password);
 
             // If password is a folder run `list` command instead
             if let Ok(path) = std::fs::metadata(&path) {
@@ -38,17 +69,38 @@ pub fn show(
         return Err(anyhow!("Password `{}` does not exist", password_name));
     }
 
+    // Write password as it is, it may not be text
+    if let Some(output) = output {
+        return write_output(&mut password, &password_name, output, force, settings);
+    }
+
     // Attempt to open and decrypt password in file
-    let key_path = settings.get_pgp_key_path()?;
-    password.open_decrypt(key_path, None)?;
+    password.open_decrypt(settings, None)?;
+
+    // Let the user know who wrote the password
+    if let Some(signature) = password.get_signature() {
+        eprintln!("Password `{}`: {}", password_name, signature);
+    }
 
-    // Get specific line if asked
-    let output = match line {
-        Some(line) => password
+    // Get specific line or field if asked
+    let field;
+    let output: &str = match part {
+        Part::All => without_line_ending(password.contents()?),
+        Part::Line(line) => password
             .line(line)
-            .context(format!("Failed to get line {} of {}", line, password_name))?
-            .into(),
-        None => password.to_string().unwrap(),
+            .context(format!("Failed to get line {} of {}", line, password_name))?,
+        Part::Field(name) => {
+            field = password
+                .field(name)
+                .context(format!("There is no field `{}` in {}", name, password_name))?;
+            &field
+        }
+        Part::FieldNames => {
+            for name in password.field_names() {
+                println!("{}", name);
+            }
+            return Ok(());
+        }
     };
 
     if qr_code {
@@ -59,43 +111,97 @@ pub fn show(
     }
 
     if clipboard {
-        return set_to_clipboard(&output, &password_name);
+        return set_to_clipboard(output, &password_name);
     }
 
-    password.display_line_padding(line.unwrap_or(1))?;
+    match part {
+        Part::Line(line) => password.display_line_padding(line)?,
+        Part::Field(_) => {
+            let first_line = output.lines().next().unwrap_or_default();
+            println!("{}", "=".repeat(first_line.len()))
+        }
+        _ => password.display_line_padding(1)?,
+    }
     println!("{}", output);
 
     Ok(())
 }
 
+/// Remove the line ending of the last line of `contents`, it is added back when displayed
+fn without_line_ending(contents: &str) -> &str {
+    match contents.strip_suffix('\n') {
+        Some(contents) => contents.strip_suffix('\r').unwrap_or(contents),
+        None => contents,
+    }
+}
+
+/// Decrypt `password` to the file at `output`, without keeping it in memory
+///
+/// An existing file is only replaced if `force` is set
+fn write_output(
+    password: &mut Password,
+    password_name: &str,
+    output: &Path,
+    force: bool,
+    settings: &Settings,
+) -> anyhow::Result<()> {
+    if output.exists() && !force {
+        return Err(anyhow!(
+            "File {} already exists, use --force to overwrite it",
+            output.display()
+        ));
+    }
+
+    // Do not leave a partial or untrusted file behind
+    let size = write_new_file(output, |file| password.decrypt_to(settings, None, file))
+        .with_context(|| format!("Failed to write file {}", output.display()))?;
+
+    // Let the user know who wrote the password
+    if let Some(signature) = password.get_signature() {
+        eprintln!("Password `{}`: {}", password_name, signature);
+    }
+
+    println!(
+        "Entry `{}` has been written to {} ({} bytes)",
+        password_name,
+        output.display(),
+        size
+    );
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::cmd::key::write_secret_key;
+    use crate::decrypt::SignatureStatus;
     use crate::password::Password;
+    use sequoia_openpgp::cert::CertBuilder;
     use tempfile::tempdir;
 
     #[test]
     fn full_password() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
-        let pgp_key = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
         let password_contents = "my_super_secure_password";
         let password_name = "password";
-        let password_store = crate::cmd::insert::tests::create_password_store(&tmp_dir.path())?;
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
         crate::cmd::git::init(&password_store)?;
 
         // create password
         let mut password = Password::from_single_line(password_contents);
         password.set_filepath(&password_store, password_name);
-        password.encrypt_with_key(pgp_key.as_ref())?;
+        password.encrypt_with_key(&settings)?;
 
         // clean password struct
         password.clean_password();
 
         // load from encrypted file with password
-        password.open_decrypt(pgp_key.as_ref(), Some("password".into()))?;
+        password.open_decrypt(&settings, Some("password".into()))?;
 
         // make sure the content is the same as input
-        assert_eq!(password.to_string()?, password_contents);
+        assert_eq!(password.contents()?, password_contents);
 
         Ok(())
     }
@@ -104,28 +210,183 @@ mod tests {
     fn specific_line() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
 
-        let pgp_key = format!("{}\\tests\\secret-key.asc", env!("CARGO_MANIFEST_DIR"));
-        let mut password_contents = Vec::new();
-        password_contents.push("multi\n".to_string());
-        password_contents.push("line\n".to_string());
-        password_contents.push("password\n".to_string());
+        let password_contents = vec![
+            "multi\n".to_string(),
+            "line\n".to_string(),
+            "password\n".to_string(),
+        ];
         let password_name = "password";
-        let password_store = crate::cmd::insert::tests::create_password_store(&tmp_dir.path())?;
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
         crate::cmd::git::init(&password_store)?;
 
         // create password
         let mut password = Password::from_multi_line(&password_contents);
         password.set_filepath(&password_store, password_name);
-        password.encrypt_with_key(pgp_key.as_ref())?;
+        password.encrypt_with_key(&settings)?;
 
         // clean password struct
         password.clean_password();
 
         // load from encrypted file with password
-        password.open_decrypt(pgp_key.as_ref(), Some("password".into()))?;
+        password.open_decrypt(&settings, Some("password".into()))?;
 
         // make sure the content is the same as input
-        assert_eq!(password.line(2).unwrap(), password_contents.get(1).unwrap());
+        assert_eq!(password.line(2).unwrap(), "line");
+
+        Ok(())
+    }
+
+    #[test]
+    fn exact_contents() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        // Windows line endings, an empty line and no newline at the end
+        let password_contents = "my_super_secure_password\r\nuser: me\n\nurl: example.com";
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let settings = crate::cmd::insert::tests::create_settings(&password_store);
+
+        let mut password = Password::default();
+        password.set_filepath(&password_store, "password");
+        password.multi_line_input(password_contents);
+        password.encrypt_with_key(&settings)?;
+        password.clean_password();
+
+        // Contents are the same bytes, lines do not have their line ending
+        password.open_decrypt(&settings, Some("password".into()))?;
+        assert_eq!(password.contents()?, password_contents);
+        assert_eq!(password.line(1).unwrap(), "my_super_secure_password");
+        assert_eq!(password.line(3).unwrap(), "");
+        assert_eq!(password.line(4).unwrap(), "url: example.com");
+
+        // Single line passwords end with a newline, like with pass
+        password.single_line_input("my_super_secure_password");
+        assert_eq!(password.contents()?, "my_super_secure_password\n");
+        assert_eq!(
+            super::without_line_ending(password.contents()?),
+            "my_super_secure_password"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fields() {
+        let password = Password::from_multi_line(&[
+            "my_super_secure_password\n".to_string(),
+            "Login: me\n".to_string(),
+            "---\n".to_string(),
+            "url: example.com\n".to_string(),
+        ]);
+
+        assert_eq!(password.field_names(), ["Login", "url"]);
+        assert_eq!(password.field("login").unwrap(), "me");
+        assert_eq!(password.field("url").unwrap(), "example.com");
+        assert_eq!(password.field("password").is_none(), true);
+    }
+
+    #[test]
+    fn output_file() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+        let mut settings = crate::cmd::insert::tests::create_settings(&password_store);
+        settings.set_passphrase_command("echo password");
+
+        let contents = (0..=255u8).cycle().take(10_000).collect::<Vec<_>>();
+        let mut password = Password::default();
+        password.set_filepath(&password_store, "keyfile");
+        password.encrypt_from(&settings, &mut contents.as_slice())?;
+
+        let output = tmp_dir.path().join("keyfile.bin");
+        std::fs::write(&output, "existing")?;
+        let show = |force| {
+            super::show(
+                Some("keyfile".to_string()),
+                super::Part::All,
+                false,
+                false,
+                Some(&output),
+                force,
+                &settings,
+            )
+        };
+
+        // Existing file is kept without `--force`
+        assert_eq!(show(false).is_err(), true);
+        assert_eq!(std::fs::read(&output)?, b"existing");
+
+        show(true)?;
+        assert_eq!(std::fs::read(&output)?, contents);
+
+        // Decrypted file and entry are only readable by the user
+        #[cfg(unix)]
+        for path in &[output.clone(), password_store.join("keyfile.gpg")] {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Nothing is left next to the file
+        assert_eq!(std::fs::read_dir(tmp_dir.path())?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn trusted_signers() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let keys_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let password_store = crate::cmd::insert::tests::create_password_store(tmp_dir.path())?;
+
+        // Trusted key, and a key planted in the keyring with the same email
+        let (trusted, _) =
+            CertBuilder::general_purpose(None, Some("signer@example.com")).generate()?;
+        let (planted, _) =
+            CertBuilder::general_purpose(None, Some("signer@example.com")).generate()?;
+        crate::keyring::import(&password_store, vec![trusted.clone(), planted.clone()])?;
+
+        let mut settings = crate::cmd::insert::tests::create_settings(&password_store);
+        settings.set_passphrase_command("echo password");
+        settings.set_require_signature(true);
+
+        // Emails and key ids are refused, they can be shared by a planted key
+        settings.set_trusted_signers(&["signer@example.com"]);
+        assert_eq!(settings.get_trusted_signers().is_err(), true);
+        settings.set_trusted_signers(&[&trusted.fingerprint().to_hex()]);
+
+        for (cert, name) in &[(&trusted, "trusted"), (&planted, "planted")] {
+            let key_path = keys_dir.path().join(format!("{}.asc", name));
+            write_secret_key(cert, &key_path)?;
+
+            let mut signer_settings = crate::cmd::insert::tests::create_settings(&password_store);
+            signer_settings.set_pgp_key_path(&key_path);
+            signer_settings.set_secret_keys(vec![secret_key_path.clone().into()]);
+            signer_settings.set_sign_passwords(true);
+
+            let mut password = Password::from_single_line("my_password");
+            password.set_filepath(&password_store, name);
+            password.encrypt_with_key(&signer_settings)?;
+        }
+
+        // Signature of the trusted key is good
+        let mut password = Password::default();
+        password.set_filepath(&password_store, "trusted");
+        password.open_decrypt(&settings, None)?;
+        assert_eq!(
+            matches!(
+                password.get_signature(),
+                Some(SignatureStatus::Good(fingerprint)) if *fingerprint == trusted.fingerprint()
+            ),
+            true
+        );
+
+        // Signature of the planted key is refused
+        let mut password = Password::default();
+        password.set_filepath(&password_store, "planted");
+        assert_eq!(password.open_decrypt(&settings, None).is_err(), true);
 
         Ok(())
     }
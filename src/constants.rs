@@ -3,3 +3,31 @@ pub const SETTINGS_FILE: &str = "pass4thewin.toml";
 pub const ID_QUALIFIER: &str = "com";
 pub const ID_ORGANIZATION: &str = "philippeloctaux";
 pub const ID_APPLICATION: &str = "pass4thewin";
+
+pub const GPG_ID_FILE: &str = ".gpg-id";
+pub const PUBLIC_KEYS_FOLDER: &str = ".public-keys";
+pub const GPG_ID_SIGNATURE_FILE: &str = ".gpg-id.sig";
+
+/// Rules for the passwords of a folder, found like `.gpg-id`
+pub const POLICY_FILE: &str = ".pass-policy";
+
+/// Same environment variable as pass, fingerprints of keys allowed to sign `.gpg-id` files
+pub const SIGNING_KEY_ENV: &str = "PASSWORD_STORE_SIGNING_KEY";
+
+/// Seconds before the agent forgets an unused key, if not set in the settings
+pub const AGENT_TIMEOUT: u64 = 600;
+
+/// Extensions of the key files in the folders of `secret_keys`
+pub const KEY_EXTENSIONS: [&str; 4] = ["asc", "gpg", "pgp", "key"];
+
+/// Password of the secret key, to decrypt without a terminal
+pub const KEY_PASSWORD_ENV: &str = "PASS4THEWIN_KEY_PASSWORD";
+
+/// File descriptor to read the password of the secret key from
+pub const KEY_PASSWORD_FD_ENV: &str = "PASS4THEWIN_KEY_PASSWORD_FD";
+
+/// Length of generated passwords, if not given
+pub const PASSWORD_LENGTH: usize = 20;
+
+/// Between the words of generated passphrases, if not given
+pub const PASSPHRASE_SEPARATOR: &str = " ";
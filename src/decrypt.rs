@@ -1,9 +1,14 @@
+use crate::agent;
+use crate::prompt;
+use crate::secret::SecretString;
 use anyhow::anyhow;
 use anyhow::Context;
-use sequoia_openpgp::crypto::{Password, SessionKey};
+use sequoia_ipc::gnupg;
+use sequoia_openpgp::crypto::{KeyPair, Password, SessionKey};
 use sequoia_openpgp::packet::{key, Key, PKESK, SKESK};
 use sequoia_openpgp::parse::stream::{
-    DecryptionHelper, DecryptorBuilder, MessageStructure, VerificationHelper,
+    DecryptionHelper, DecryptorBuilder, MessageLayer, MessageStructure, VerificationError,
+    VerificationHelper,
 };
 use sequoia_openpgp::parse::Parse;
 use sequoia_openpgp::policy::{Policy, StandardPolicy};
@@ -11,48 +16,171 @@ use sequoia_openpgp::types::SymmetricAlgorithm;
 use sequoia_openpgp::Result;
 use sequoia_openpgp::{crypto, KeyID};
 use sequoia_openpgp::{Cert, Fingerprint, KeyHandle};
+use std::io::Read;
 use std::path::Path;
 
-struct Helper {
-    cert: Cert,
-    secret_keys: Vec<Key<key::SecretParts, key::UnspecifiedRole>>,
-    password: Option<String>,
+/// Gives the password of the secret keys, `None` if it has to be asked
+pub type PasswordSource<'a> = &'a dyn Fn() -> Result<Option<SecretString>>;
+
+struct Helper<'a> {
+    // Name of the decrypted entry, shown when asking for a password
+    entry: String,
+
+    // Our certificates, with their secret keys unless gpg-agent has them
+    certs: Vec<Cert>,
+
+    // Encryption keys of `certs` which can be used to decrypt
+    keys: Vec<Key<key::PublicParts, key::UnspecifiedRole>>,
+
+    // Gives the password of the secret keys, only used when a key has to be unlocked
+    password_source: Option<PasswordSource<'a>>,
+
+    // Password of the secret keys, once it is known
+    password: Option<SecretString>,
+
+    // Passphrase of the file if it is symmetrically encrypted, it is asked if there is none
+    given_passphrase: Option<Password>,
+
+    // Secret keys are in gpg-agent instead of `cert`
+    gpg_agent: Option<&'a gnupg::Context>,
+
+    // Passphrase which decrypted the file, if it is symmetrically encrypted
+    passphrase: Option<Password>,
+
+    // Keys trusted to sign files
+    signers: Vec<Cert>,
+
+    // Result of the verification of the signatures of the file
+    signature: SignatureStatus,
+}
+
+/// Result of the verification of the signatures of a decrypted file
+#[derive(Debug, PartialEq)]
+pub enum SignatureStatus {
+    /// Good signature from a trusted key
+    Good(Fingerprint),
+
+    /// Signature from a key which is not trusted, with the key ids of the signers
+    Unknown(Vec<KeyID>),
+
+    /// Signature is invalid
+    Bad(String),
+
+    /// File is not signed
+    Unsigned,
+}
+
+impl std::fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SignatureStatus::Good(fingerprint) => write!(f, "good signature from {}", fingerprint),
+            SignatureStatus::Unknown(issuers) => write!(
+                f,
+                "signature from untrusted key {}",
+                issuers
+                    .iter()
+                    .map(|issuer| issuer.to_hex())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            SignatureStatus::Bad(error) => write!(f, "bad signature ({})", error),
+            SignatureStatus::Unsigned => write!(f, "not signed"),
+        }
+    }
+}
+
+/// Result of the decryption of a file
+pub struct Decrypted<T = SecretString> {
+    /// Contents of the file, or what was made of them
+    pub contents: T,
+
+    /// Passphrase used to decrypt the file, if it is symmetrically encrypted
+    pub passphrase: Option<Password>,
+
+    /// Signature of the file
+    pub signature: SignatureStatus,
 }
 
-impl VerificationHelper for Helper {
+impl<'a> VerificationHelper for Helper<'a> {
     fn get_certs(&mut self, _ids: &[KeyHandle]) -> Result<Vec<Cert>> {
-        Ok(Vec::new())
+        Ok(self.signers.clone())
     }
 
-    fn check(&mut self, _structure: MessageStructure) -> Result<()> {
+    /// Keep the best result, the file is decrypted even if signatures are not good
+    fn check(&mut self, structure: MessageStructure) -> Result<()> {
+        for layer in structure.into_iter() {
+            if let MessageLayer::SignatureGroup { results } = layer {
+                for result in results {
+                    let status = match result {
+                        // Fingerprint of the certificate that made the signature
+                        Ok(good) => SignatureStatus::Good(good.ka.cert().fingerprint()),
+                        Err(VerificationError::MissingKey { sig }) => {
+                            SignatureStatus::Unknown(sig.issuers().cloned().collect())
+                        }
+                        Err(e) => SignatureStatus::Bad(e.to_string()),
+                    };
+
+                    let better = match (&self.signature, &status) {
+                        (SignatureStatus::Good(_), _) => false,
+                        (_, SignatureStatus::Good(_)) => true,
+                        (SignatureStatus::Unsigned, _) => true,
+                        (SignatureStatus::Unknown(_), SignatureStatus::Bad(_)) => true,
+                        _ => false,
+                    };
+                    if better {
+                        self.signature = status;
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 }
 
-impl Helper {
-    fn new(policy: &dyn Policy, cert: Cert, password: Option<String>) -> Self {
-        // Import all secrets keys found in cert
-        let secret_keys = cert
-            .keys()
-            .with_policy(policy, None)
-            .for_transport_encryption()
-            .for_storage_encryption()
-            .secret()
-            .into_iter()
-            .map(|key| key.key().clone())
+impl<'a> Helper<'a> {
+    fn new(
+        policy: &dyn Policy,
+        entry: String,
+        certs: &[Cert],
+        password_source: PasswordSource<'a>,
+        given_passphrase: Option<&Password>,
+        signers: &[Cert],
+        gpg_agent: Option<&'a gnupg::Context>,
+    ) -> Self {
+        // Import encryption keys found in certs, gpg-agent has the secrets of public keys
+        let keys = certs
+            .iter()
+            .flat_map(|cert| {
+                cert.keys()
+                    .with_policy(policy, None)
+                    .for_transport_encryption()
+                    .for_storage_encryption()
+                    .filter(|key| gpg_agent.is_some() || key.key().has_secret())
+                    .map(|key| key.key().clone())
+                    .collect::<Vec<_>>()
+            })
             .collect::<Vec<_>>();
 
         Self {
-            cert,
-            secret_keys,
-            password,
+            entry,
+            certs: certs.to_vec(),
+            keys,
+            password_source: Some(password_source),
+            password: None,
+            given_passphrase: given_passphrase.cloned(),
+            gpg_agent,
+            passphrase: None,
+            signers: signers.to_vec(),
+            signature: SignatureStatus::Unsigned,
         }
     }
 
-    /// Try to decrypt PKESK packet with `keypair`
+    /// Try to decrypt PKESK packet with `keypair` of `key`
     /// And try to decrypt packet parser with `decrypt`
     fn try_decrypt<D>(
         &self,
+        key: &Key<key::PublicParts, key::UnspecifiedRole>,
         pkesk: &PKESK,
         sym_algo: Option<SymmetricAlgorithm>,
         keypair: &mut dyn crypto::Decryptor,
@@ -61,7 +189,7 @@ impl Helper {
     where
         D: FnMut(SymmetricAlgorithm, &SessionKey) -> bool,
     {
-        match pkesk
+        pkesk
             .decrypt(keypair, sym_algo)
             .and_then(|(algo, session_key)| {
                 if decrypt(algo, &session_key) {
@@ -69,119 +197,656 @@ impl Helper {
                 } else {
                     None
                 }
-            }) {
-            Some(_session_key) => Some(self.cert.fingerprint()),
-            None => None,
+            })
+            .map(|_session_key| key.fingerprint())
+    }
+
+    /// Get the password of the secret keys from its source, the first time it is needed
+    fn password(&mut self) -> Result<Option<&str>> {
+        if let Some(source) = self.password_source.take() {
+            self.password = source()?;
+        }
+
+        Ok(self.password.as_deref())
+    }
+
+    /// Get something to decrypt session keys with `key`,
+    /// either gpg-agent or the secret key from the file
+    fn decryptor<'k>(
+        &mut self,
+        key: &'k Key<key::PublicParts, key::UnspecifiedRole>,
+    ) -> Result<Box<dyn crypto::Decryptor + 'k>> {
+        match self.gpg_agent {
+            Some(context) => Ok(Box::new(gnupg::KeyPair::new(context, key)?)),
+            None => {
+                let secret_key = key.clone().parts_into_secret()?;
+                if !secret_key.secret().is_encrypted() {
+                    return Ok(Box::new(secret_key.into_keypair()?));
+                }
+
+                let reason = format!("decrypt {}", self.entry);
+                let password = self.password()?;
+                let prompted = password.is_none();
+                let secret_key = unlock_secret_key(secret_key, password, &reason)?;
+
+                // Keep key unlocked with a prompt in the agent if it is running
+                if prompted {
+                    let _ = agent::put_key(&secret_key);
+                }
+
+                Ok(Box::new(secret_key.into_keypair()?))
+            }
         }
     }
 }
 
-/// Go through secret keys loaded from file
+/// Go through keys loaded from file
 /// and return the Key whose KeyID
 /// matches with the KeyID of the recipient.
 ///
 /// Return `None` if no key matches
-fn get_secret_key_for_recipient(
-    secret_keys: &[Key<key::SecretParts, key::UnspecifiedRole>],
+fn get_key_for_recipient(
+    keys: &[Key<key::PublicParts, key::UnspecifiedRole>],
     recipient: KeyID,
-) -> Option<Key<key::SecretParts, key::UnspecifiedRole>> {
-    for key in secret_keys {
-        if KeyID::from(key.fingerprint()) == recipient {
-            return Some(key.clone());
+) -> Option<&Key<key::PublicParts, key::UnspecifiedRole>> {
+    keys.iter()
+        .find(|key| KeyID::from(key.fingerprint()) == recipient)
+}
+
+/// Decrypt secret material of `secret_key` and turn it into a keypair
+///
+/// Uses `password` if there is one, or asks for a password, telling the `reason` why the key is needed
+pub fn unlock_key(
+    secret_key: Key<key::SecretParts, key::UnspecifiedRole>,
+    password: Option<&str>,
+    reason: &str,
+) -> Result<KeyPair> {
+    unlock_secret_key(secret_key, password, reason)?.into_keypair()
+}
+
+/// Decrypt secret material of `secret_key`, like `unlock_key`
+fn unlock_secret_key(
+    mut secret_key: Key<key::SecretParts, key::UnspecifiedRole>,
+    password: Option<&str>,
+    reason: &str,
+) -> Result<Key<key::SecretParts, key::UnspecifiedRole>> {
+    // Try to use secret key without prompting for a password
+    if !secret_key.secret().is_encrypted() {
+        return Ok(secret_key);
+    }
+
+    let algo = secret_key.pk_algo();
+    match password {
+        Some(password) => {
+            // If we already have a password to try against
+            let password = Password::from(password);
+            if secret_key
+                .secret_mut()
+                .decrypt_in_place(algo, &password)
+                .is_err()
+            {
+                return Err(anyhow!("Invalid password for key"));
+            }
+        }
+        None => {
+            let description = format!(
+                "Enter password of key {} to {}",
+                secret_key.fingerprint(),
+                reason
+            );
+            let mut error = None;
+            loop {
+                // Loop and ask for a password until we get a good one or user aborts decryption
+                let password = prompt::password(&description, "Password", error)?.into();
+
+                if let Ok(()) = secret_key.secret_mut().decrypt_in_place(algo, &password) {
+                    break;
+                } else {
+                    error = Some("Bad password, please try again");
+                }
+            }
         }
     }
-    None
+
+    Ok(secret_key)
 }
 
-impl DecryptionHelper for Helper {
+impl<'a> Helper<'a> {
+    /// Try to decrypt the session key contained in SKESK packets with a passphrase
+    ///
+    /// Uses the passphrase given when creating the helper, or asks for one.
+    /// The password of the keys is never used as a passphrase
+    fn decrypt_symmetric<D>(&mut self, skesks: &[SKESK], decrypt: &mut D) -> Result<()>
+    where
+        D: FnMut(SymmetricAlgorithm, &SessionKey) -> bool,
+    {
+        let description = format!("Enter passphrase of {}", self.entry);
+        let mut error = None;
+        loop {
+            let passphrase: Password = match &self.given_passphrase {
+                Some(passphrase) => passphrase.clone(),
+                None => prompt::password(&description, "Passphrase", error)?.into(),
+            };
+
+            // A wrong passphrase gives a wrong session key, `decrypt` will reject it
+            for skesk in skesks {
+                if let Ok((algo, session_key)) = skesk.decrypt(&passphrase) {
+                    if decrypt(algo, &session_key) {
+                        self.passphrase = Some(passphrase);
+                        return Ok(());
+                    }
+                }
+            }
+
+            if self.given_passphrase.is_some() {
+                return Err(anyhow!("Invalid passphrase for entry"));
+            }
+            error = Some("Bad passphrase, please try again");
+        }
+    }
+}
+
+impl<'a> DecryptionHelper for Helper<'a> {
     fn decrypt<D>(
         &mut self,
         pkesks: &[PKESK],
-        _skesks: &[SKESK],
+        skesks: &[SKESK],
         sym_algo: Option<SymmetricAlgorithm>,
         mut decrypt: D,
     ) -> Result<Option<Fingerprint>>
     where
         D: FnMut(SymmetricAlgorithm, &SessionKey) -> bool,
     {
-        // PKESK is the session key
-        let session_key = &pkesks[0];
-        let recipient_keyid = session_key.recipient().clone();
+        // Each PKESK contains the session key, encrypted for one recipient
+        // Keep the ones encrypted for one of our keys
+        let mut candidates = Vec::new();
+        for pkesk in pkesks {
+            let recipient = pkesk.recipient();
 
-        // Get secret key to use to decrypt file
-        let mut secret_key = get_secret_key_for_recipient(&self.secret_keys, recipient_keyid)
-            .context("Could not find key to decrypt file")?;
+            if recipient.is_wildcard() {
+                // Anonymous recipient, it could be any of our keys
+                for key in &self.keys {
+                    candidates.push((pkesk, key.clone()));
+                }
+            } else if let Some(key) = get_key_for_recipient(&self.keys, recipient.clone()) {
+                candidates.push((pkesk, key.clone()));
+            }
+        }
 
-        // Try to use secret key without prompting for a password
-        if !secret_key.secret().is_encrypted() {
-            if let Some(fingerprint) =
-                secret_key
-                    .clone()
-                    .into_keypair()
-                    .ok()
-                    .and_then(|mut keypair| {
-                        self.try_decrypt(session_key, sym_algo, &mut keypair, &mut decrypt)
-                    })
-            {
-                return Ok(Some(fingerprint));
+        if candidates.is_empty() && skesks.is_empty() {
+            // Public keys are enough to encrypt, not to decrypt
+            if self.gpg_agent.is_none() && !self.certs.iter().any(|cert| cert.is_tsk()) {
+                return Err(anyhow!(
+                    "There are only public keys, a secret key is needed to decrypt passwords"
+                ));
             }
+
+            let recipients = pkesks
+                .iter()
+                .map(|pkesk| pkesk.recipient().to_hex())
+                .collect::<Vec<_>>();
+
+            return Err(anyhow!(
+                "Could not find key to decrypt file, it is encrypted for key ids: {}",
+                recipients.join(", ")
+            ));
         }
 
-        let keypair = {
-            match &self.password {
-                Some(password) => {
-                    // If we already have a password to try against
-                    let algo = secret_key.pk_algo();
-                    let password = Password::from(password.clone());
-                    if let Ok(()) = secret_key.secret_mut().decrypt_in_place(algo, &password) {
-                        Some(secret_key.clone().into_keypair()?)
-                    } else {
-                        None
+        // Try each of our keys until the session key is decrypted,
+        // a key which can not be used does not prevent trying the next ones
+        for (pkesk, key) in candidates {
+            // Key unlocked in our agent, which decrypts the session key itself,
+            // the password of the key is not needed
+            if self.gpg_agent.is_none()
+                && key
+                    .optional_secret()
+                    .is_some_and(|secret| secret.is_encrypted())
+            {
+                if let Some((algo, session_key)) =
+                    agent::decrypt(&key.fingerprint(), pkesk, sym_algo)
+                {
+                    if decrypt(algo, &session_key) {
+                        return Ok(Some(key.fingerprint()));
                     }
                 }
-                None => loop {
-                    // Loop and ask for a password until we get a good one or user aborts decryption
-                    let password =
-                        rpassword::read_password_from_tty(Some("Enter password to decrypt key: "))?
-                            .into();
-
-                    let algo = secret_key.pk_algo();
-                    if let Ok(()) = secret_key.secret_mut().decrypt_in_place(algo, &password) {
-                        break Some(secret_key.clone().into_keypair()?);
-                    } else {
-                        eprintln!("Bad password. Please try again (press Ctrl+C to cancel)")
-                    }
-                },
             }
-        };
 
-        if keypair.is_none() {
-            return Err(anyhow!("Invalid password for key"));
+            let mut decryptor = match self.decryptor(&key) {
+                Ok(decryptor) => decryptor,
+                Err(e) => {
+                    eprintln!("Failed to use key {}: {}", key.fingerprint(), e);
+                    continue;
+                }
+            };
+
+            if let Some(fingerprint) =
+                self.try_decrypt(&key, pkesk, sym_algo, decryptor.as_mut(), &mut decrypt)
+            {
+                return Ok(Some(fingerprint));
+            }
         }
 
-        if let Some(fingerprint) =
-            self.try_decrypt(session_key, sym_algo, &mut keypair.unwrap(), &mut decrypt)
-        {
-            return Ok(Some(fingerprint));
+        // Entry is (also) protected by a passphrase
+        if !skesks.is_empty() {
+            self.decrypt_symmetric(skesks, &mut decrypt)?;
+            return Ok(None);
         }
 
-        Ok(None)
+        Err(anyhow!("Failed to decrypt file with the available keys"))
     }
 }
 
-pub fn decrypt(encrypted_path: &Path, key_path: &Path, password: Option<String>) -> Result<String> {
+/// Decrypt file `encrypted_path` of entry `entry` with the first key of `certs` it is encrypted for
+///
+/// `password_source` gives the password unlocking the keys, it is not used for keys unlocked
+/// in the agent. `passphrase` decrypts the file if it is symmetrically encrypted.
+/// `read` gets the plaintext as it is decrypted, and the passphrase of the file if it has one
+#[allow(clippy::too_many_arguments)]
+pub fn decrypt_with<T>(
+    encrypted_path: &Path,
+    entry: &str,
+    certs: &[Cert],
+    password_source: PasswordSource,
+    passphrase: Option<&Password>,
+    signers: &[Cert],
+    gpg_agent: Option<&gnupg::Context>,
+    read: impl FnOnce(&mut dyn Read, Option<&Password>) -> Result<T>,
+) -> Result<Decrypted<T>> {
     let policy = &mut StandardPolicy::new();
-    let cert = Cert::from_file(key_path).context("Failed to load key from file")?;
 
-    let helper = Helper::new(policy, cert, password);
+    let helper = Helper::new(
+        policy,
+        entry.to_string(),
+        certs,
+        password_source,
+        passphrase,
+        signers,
+        gpg_agent,
+    );
 
     let decryptor = DecryptorBuilder::from_file(encrypted_path)
         .context(format!("Failed to open file {}", encrypted_path.display()))?;
 
     let mut decryptor = decryptor.with_policy(policy, None, helper)?;
 
-    let mut writer: Vec<u8> = vec![];
-    std::io::copy(&mut decryptor, &mut writer)?;
+    // Passphrase is known once the session key is decrypted, before reading the contents
+    let passphrase = decryptor.helper_ref().passphrase.clone();
+    let contents = read(&mut decryptor, passphrase.as_ref())?;
+
+    let helper = decryptor.into_helper();
+
+    Ok(Decrypted {
+        contents,
+        passphrase: helper.passphrase,
+        signature: helper.signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decrypt::{Decrypted, SignatureStatus};
+    use crate::encrypt::{encrypt, encrypt_symmetric};
+    use crate::password::Password;
+    use crate::recipients::load_certs;
+    use crate::secret::{read_to_secret, SecretString};
+    use crate::settings::Settings;
+    use crate::sign::signing_keypair;
+    use anyhow::anyhow;
+    use sequoia_ipc::gnupg;
+    use sequoia_openpgp::cert::CertBuilder;
+    use sequoia_openpgp::Cert;
+    use std::fs::File;
+    use std::path::Path;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    /// Decrypt file as text
+    fn decrypt(
+        encrypted_path: &Path,
+        certs: &[Cert],
+        password: Option<SecretString>,
+        signers: &[Cert],
+        gpg_agent: Option<&gnupg::Context>,
+    ) -> anyhow::Result<Decrypted> {
+        super::decrypt_with(
+            encrypted_path,
+            "password",
+            certs,
+            &|| Ok(password.clone()),
+            None,
+            signers,
+            gpg_agent,
+            |plaintext, _| read_to_secret(plaintext),
+        )
+    }
+
+    #[test]
+    fn several_recipients() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let mut certs = load_certs(secret_key_path.as_ref())?;
+
+        // Other recipient comes first
+        let (other, _) =
+            CertBuilder::general_purpose(None, Some("other@example.com")).generate()?;
+        certs.insert(0, other.clone());
+
+        let encrypted_path = tmp_dir.path().join("password.gpg");
+        encrypt(
+            &mut "my_password".as_bytes(),
+            &mut File::create(&encrypted_path)?,
+            &certs,
+            None,
+        )?;
+
+        let decrypted = decrypt(
+            &encrypted_path,
+            &load_certs(secret_key_path.as_ref())?,
+            Some("password".into()),
+            &[],
+            None,
+        )?;
+        assert_eq!(decrypted.contents, "my_password");
+        assert_eq!(decrypted.passphrase.is_none(), true);
+
+        // Not encrypted for our key
+        encrypt(
+            &mut "my_password".as_bytes(),
+            &mut File::create(&encrypted_path)?,
+            std::slice::from_ref(&other),
+            None,
+        )?;
+        let result = decrypt(
+            &encrypted_path,
+            &load_certs(secret_key_path.as_ref())?,
+            Some("password".into()),
+            &[],
+            None,
+        );
+        assert_eq!(result.is_err(), true);
+
+        // Key matching the recipient is chosen among our keys
+        let decrypted = decrypt(
+            &encrypted_path,
+            &[certs[1].clone(), other],
+            Some("password".into()),
+            &[],
+            None,
+        )?;
+        assert_eq!(decrypted.contents, "my_password");
+
+        Ok(())
+    }
+
+    #[test]
+    fn locked_key() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let mut certs = load_certs(secret_key_path.as_ref())?;
+
+        // Other key has another password, and comes first
+        let (other, _) = CertBuilder::general_purpose(None, Some("other@example.com"))
+            .set_password(Some("other password".into()))
+            .generate()?;
+        certs.insert(0, other);
+
+        let encrypted_path = tmp_dir.path().join("password.gpg");
+        encrypt(
+            &mut "my_password".as_bytes(),
+            &mut File::create(&encrypted_path)?,
+            &certs,
+            None,
+        )?;
+
+        let decrypted = decrypt(&encrypted_path, &certs, Some("password".into()), &[], None)?;
+        assert_eq!(decrypted.contents, "my_password");
+
+        // No key can be unlocked
+        let result = decrypt(&encrypted_path, &certs, Some("wrong".into()), &[], None);
+        assert_eq!(result.is_err(), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn password_source() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        // Key without password
+        let (cert, _) = CertBuilder::general_purpose(None, Some("other@example.com")).generate()?;
+        let certs = vec![cert];
+
+        let encrypted_path = tmp_dir.path().join("password.gpg");
+        encrypt(
+            &mut "my_password".as_bytes(),
+            &mut File::create(&encrypted_path)?,
+            &certs,
+            None,
+        )?;
+
+        // Source of the password is only used when a key has to be unlocked
+        let decrypted = super::decrypt_with(
+            &encrypted_path,
+            "password",
+            &certs,
+            &|| Err(anyhow!("Password should not be needed")),
+            None,
+            &[],
+            None,
+            |plaintext, _| read_to_secret(plaintext),
+        )?;
+        assert_eq!(decrypted.contents, "my_password");
+
+        Ok(())
+    }
+
+    #[test]
+    fn symmetric() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let certs = load_certs(secret_key_path.as_ref())?;
+
+        let encrypted_path = tmp_dir.path().join("password.gpg");
+        encrypt_symmetric(
+            &mut "my_password".as_bytes(),
+            &mut File::create(&encrypted_path)?,
+            &"break glass".into(),
+            None,
+        )?;
+
+        let decrypt = |passphrase: &str| {
+            super::decrypt_with(
+                &encrypted_path,
+                "password",
+                &certs,
+                &|| Ok(Some("password".into())),
+                Some(&passphrase.into()),
+                &[],
+                None,
+                |plaintext, _| read_to_secret(plaintext),
+            )
+        };
+
+        // Wrong passphrase, the password of the key is not the passphrase
+        assert_eq!(decrypt("password").is_err(), true);
+
+        let decrypted = decrypt("break glass")?;
+        assert_eq!(decrypted.contents, "my_password");
+        assert_eq!(decrypted.passphrase.is_some(), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn binary() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let certs = load_certs(secret_key_path.as_ref())?;
+
+        // Not valid UTF-8
+        let contents = (0..=255u8).cycle().take(100_000).collect::<Vec<_>>();
+
+        let encrypted_path = tmp_dir.path().join("password.gpg");
+        encrypt(
+            &mut contents.as_slice(),
+            &mut File::create(&encrypted_path)?,
+            &certs,
+            None,
+        )?;
+
+        let mut output = Vec::new();
+        let decrypted = super::decrypt_with(
+            &encrypted_path,
+            "password",
+            &certs,
+            &|| Ok(Some("password".into())),
+            None,
+            &[],
+            None,
+            |plaintext, _| Ok(std::io::copy(plaintext, &mut output)?),
+        )?;
+        assert_eq!(decrypted.contents, contents.len() as u64);
+        assert_eq!(output, contents);
+
+        // Not text
+        let result = decrypt(&encrypted_path, &certs, Some("password".into()), &[], None);
+        assert_eq!(result.is_err(), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn signature() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let certs = load_certs(secret_key_path.as_ref())?;
+        let (other, _) =
+            CertBuilder::general_purpose(None, Some("other@example.com")).generate()?;
+
+        let encrypted_path = tmp_dir.path().join("password.gpg");
+        let decrypt = |signers: &[Cert]| {
+            decrypt(
+                &encrypted_path,
+                &load_certs(secret_key_path.as_ref())?,
+                Some("password".into()),
+                signers,
+                None,
+            )
+        };
+
+        // Not signed
+        encrypt(
+            &mut "my_password".as_bytes(),
+            &mut File::create(&encrypted_path)?,
+            &certs,
+            None,
+        )?;
+        assert_eq!(decrypt(&certs)?.signature, SignatureStatus::Unsigned);
+
+        // Signed by our key
+        let signer = signing_keypair(&certs[0], Some("password"))?;
+        encrypt(
+            &mut "my_password".as_bytes(),
+            &mut File::create(&encrypted_path)?,
+            &certs,
+            Some(signer),
+        )?;
+        assert_eq!(
+            decrypt(&certs)?.signature,
+            SignatureStatus::Good(certs[0].fingerprint())
+        );
 
-    Ok(String::from_utf8(writer)?)
+        // Our key is not trusted
+        match decrypt(&[other])?.signature {
+            SignatureStatus::Unknown(_) => (),
+            status => panic!("Unexpected signature status: {}", status),
+        }
+
+        Ok(())
+    }
+
+    /// Needs GnuPG, skipped when it is not installed
+    #[test]
+    fn gpg_agent() -> anyhow::Result<()> {
+        if Command::new("gpg").arg("--version").output().is_err() {
+            eprintln!("gpg is not installed, skipping test");
+            return Ok(());
+        }
+
+        let tmp_dir = tempdir()?;
+        let gnupg_home = tempdir()?;
+
+        let gpg = |args: &[&str]| -> anyhow::Result<Vec<u8>> {
+            let output = Command::new("gpg")
+                .arg("--homedir")
+                .arg(gnupg_home.path())
+                .args(["--batch", "--pinentry-mode", "loopback"])
+                .args(args)
+                .output()?;
+            if !output.status.success() {
+                return Err(anyhow!("{}", String::from_utf8_lossy(&output.stderr)));
+            }
+            Ok(output.stdout)
+        };
+
+        // Key only exists in gpg-agent
+        gpg(&[
+            "--passphrase",
+            "",
+            "--quick-generate-key",
+            "agent@example.com",
+            "default",
+            "default",
+        ])?;
+        let public_key_path = tmp_dir.path().join("public-key.asc");
+        std::fs::write(
+            &public_key_path,
+            gpg(&["--armor", "--export", "agent@example.com"])?,
+        )?;
+        let certs = load_certs(&public_key_path)?;
+
+        let encrypted_path = tmp_dir.path().join("password.gpg");
+        encrypt(
+            &mut "my_password".as_bytes(),
+            &mut File::create(&encrypted_path)?,
+            &certs,
+            None,
+        )?;
+
+        let context = gnupg::Context::with_homedir(gnupg_home.path())?;
+        let decrypted = decrypt(&encrypted_path, &certs, None, &[], Some(&context));
+
+        // Same through the settings, with the public key as pgp key
+        let store_path = tmp_dir.path().join("store");
+        std::fs::create_dir_all(&store_path)?;
+        std::fs::write(store_path.join(".gpg-id"), "agent@example.com\n")?;
+        let mut settings = Settings::default();
+        settings.set_password_store_path(&store_path);
+        settings.set_pgp_key_path(&public_key_path);
+        settings.set_gpg_agent(gnupg_home.path());
+
+        let mut password = Password::from_single_line("my_other_password");
+        password.set_filepath(&store_path, "password");
+        let opened = password.encrypt_with_key(&settings).and_then(|_| {
+            password.clean_password();
+            password.open_decrypt(&settings, None)
+        });
+
+        Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(gnupg_home.path())
+            .args(["--kill", "gpg-agent"])
+            .status()?;
+
+        assert_eq!(decrypted?.contents, "my_password");
+        opened?;
+        assert_eq!(password.contents()?, "my_other_password");
+
+        Ok(())
+    }
 }
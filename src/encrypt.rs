@@ -1,39 +1,87 @@
+use anyhow::anyhow;
+use sequoia_openpgp::crypto::{KeyPair, Password};
 use sequoia_openpgp::policy::StandardPolicy;
-use sequoia_openpgp::serialize::stream::{Encryptor, LiteralWriter, Message};
+use sequoia_openpgp::serialize::stream::{Encryptor2, LiteralWriter, Message, Signer};
 use sequoia_openpgp::Cert;
-use std::io::Write;
+use std::io::{Read, Write};
+
+/// Write `plaintext` in an encrypted `message`, signed by `signer` if there is one
+///
+/// `plaintext` is read as it is encrypted
+fn write_literal(
+    message: Message,
+    plaintext: &mut dyn Read,
+    signer: Option<KeyPair>,
+) -> sequoia_openpgp::Result<()> {
+    // Sign data inside the encryption layer
+    let message = match signer {
+        Some(keypair) => Signer::new(message, keypair).build()?,
+        None => message,
+    };
+
+    // Emit literal data packet
+    let mut message = LiteralWriter::new(message).build()?;
+
+    // Encrypt data
+    std::io::copy(plaintext, &mut message)?;
+
+    // Finish OpenPGP message
+    message.finalize()?;
+
+    Ok(())
+}
 
 pub fn encrypt(
-    plaintext: &str,
-    ciphertext: &mut dyn Write,
-    recipient: &Cert,
+    plaintext: &mut dyn Read,
+    ciphertext: &mut (dyn Write + Send + Sync),
+    recipients: &[Cert],
+    signer: Option<KeyPair>,
 ) -> sequoia_openpgp::Result<()> {
     let policy = &mut StandardPolicy::new();
 
-    // Get recipient key
-    let recipients = recipient
-        .keys()
-        .with_policy(policy, None)
-        .for_transport_encryption()
-        .for_storage_encryption()
-        .secret()
-        .alive()
-        .revoked(false);
+    // Get encryption keys of every recipient
+    let mut recipient_keys = Vec::new();
+    for cert in recipients {
+        let keys = cert
+            .keys()
+            .with_policy(policy, None)
+            .for_transport_encryption()
+            .for_storage_encryption()
+            .alive()
+            .revoked(false)
+            .collect::<Vec<_>>();
+
+        if keys.is_empty() {
+            return Err(anyhow!(
+                "Key {} can not be used for encryption",
+                cert.fingerprint()
+            ));
+        }
+
+        recipient_keys.extend(keys);
+    }
 
     // Start OpenPGP message
     let message = Message::new(ciphertext);
 
     // Define recipients of the message
-    let message = Encryptor::for_recipients(message, recipients).build()?;
+    let message = Encryptor2::for_recipients(message, recipient_keys).build()?;
 
-    // Emit literal data packet
-    let mut message = LiteralWriter::new(message).build()?;
+    write_literal(message, plaintext, signer)
+}
 
-    // Encrypt data
-    message.write_all(plaintext.as_bytes())?;
+/// Encrypt `plaintext` with a passphrase only, no key is needed to decrypt it
+pub fn encrypt_symmetric(
+    plaintext: &mut dyn Read,
+    ciphertext: &mut (dyn Write + Send + Sync),
+    passphrase: &Password,
+    signer: Option<KeyPair>,
+) -> sequoia_openpgp::Result<()> {
+    // Start OpenPGP message
+    let message = Message::new(ciphertext);
 
-    // Finish OpenPGP message
-    message.finalize()?;
+    // Protect the message with the passphrase
+    let message = Encryptor2::with_passwords(message, Some(passphrase.clone())).build()?;
 
-    Ok(())
+    write_literal(message, plaintext, signer)
 }
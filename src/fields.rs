@@ -0,0 +1,509 @@
+use crate::secret::SecretString;
+use anyhow::anyhow;
+use std::ops::Range;
+
+/// Line of the contents of a password, and where it is in the contents
+struct Line<'a> {
+    // Text of the line, without its line ending
+    text: &'a str,
+
+    // Line ending, empty for a last line without one
+    ending: &'a str,
+
+    // Position of the line in the contents, with its line ending
+    span: Range<usize>,
+}
+
+/// Field of the contents of a password
+struct Field<'a> {
+    name: &'a str,
+    value: SecretString,
+
+    // Lines of the field in the contents
+    span: Range<usize>,
+
+    // Line ending of the last line of the field
+    ending: &'a str,
+
+    // Field is in the YAML part of the contents
+    yaml: bool,
+}
+
+/// Split `contents` in lines, like `str::lines` but keeping where they are
+fn lines(contents: &str) -> Vec<Line<'_>> {
+    let mut start = 0;
+
+    contents
+        .split_inclusive('\n')
+        .map(|line| {
+            let text = line.trim_end_matches('\n');
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            let span = start..start + line.len();
+            start = span.end;
+
+            Line {
+                text,
+                ending: &line[text.len()..],
+                span,
+            }
+        })
+        .collect()
+}
+
+/// Index of the `---` line starting the YAML part of the contents, if there is one
+fn yaml_separator(lines: &[Line]) -> Option<usize> {
+    lines
+        .iter()
+        .skip(1)
+        .position(|line| line.text.trim_end() == "---")
+        .map(|i| i + 1)
+}
+
+/// Fields of the contents of a password, with their names
+///
+/// Lines after the first one are `name: value` fields, like `login: me` or `url: example.com`.
+/// After a `---` line, fields are read as a YAML mapping, like gopass does
+pub fn parse(contents: &str) -> Vec<(&str, SecretString)> {
+    parse_fields(&lines(contents))
+        .into_iter()
+        .map(|field| (field.name, field.value))
+        .collect()
+}
+
+fn parse_fields<'a>(lines: &[Line<'a>]) -> Vec<Field<'a>> {
+    let mut fields = Vec::new();
+
+    // First line is the password
+    let separator = yaml_separator(lines);
+    let end = separator.unwrap_or(lines.len());
+
+    // Other lines are free text
+    for line in lines.get(1..end).unwrap_or_default() {
+        if let Some((name, value)) = split_field(line.text) {
+            fields.push(Field {
+                name,
+                value: value.trim().into(),
+                span: line.span.clone(),
+                ending: line.ending,
+                yaml: false,
+            });
+        }
+    }
+
+    if let Some(separator) = separator {
+        fields.extend(parse_yaml(&lines[separator + 1..]));
+    }
+
+    fields
+}
+
+/// Set field `name` of `contents` to `value`, the other lines are kept as they are
+///
+/// An existing field is changed where it is, a new one is added at the end
+pub fn set(contents: &str, name: &str, value: &str) -> anyhow::Result<SecretString> {
+    if name.is_empty() || name.trim() != name || name.contains(&[':', '\n'][..]) {
+        return Err(anyhow!("`{}` is not a valid field name", name));
+    }
+
+    let lines = lines(contents);
+    let (span, name, ending, yaml) = match find(&lines, name) {
+        Some(field) => (field.span, field.name, field.ending, field.yaml),
+        None => {
+            // New line is added after the last one, which may not end
+            let ending = if contents.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            };
+            let yaml = yaml_separator(&lines).is_some();
+            (contents.len()..contents.len(), name, ending, yaml)
+        }
+    };
+
+    let value = if yaml {
+        yaml_scalar(value)
+    } else if value.contains(&['\n', '\r'][..]) {
+        return Err(anyhow!(
+            "Value of field `{}` has several lines, it can only be set after a `---` line",
+            name
+        ));
+    } else {
+        value.into()
+    };
+
+    let mut updated = SecretString::with_capacity(contents.len() + name.len() + value.len() + 4);
+    updated.push_str(&contents[..span.start]);
+    if span.start == contents.len() && !contents.ends_with('\n') {
+        updated.push_str(ending);
+    }
+    updated.push_str(name);
+    updated.push(':');
+    if !value.is_empty() {
+        updated.push(' ');
+        updated.push_str(&value);
+    }
+    updated.push_str(ending);
+    updated.push_str(&contents[span.end..]);
+
+    Ok(updated)
+}
+
+/// Remove field `name` of `contents`, the other lines are kept as they are
+///
+/// Returns `None` if there is no such field
+pub fn unset(contents: &str, name: &str) -> Option<SecretString> {
+    let span = find(&lines(contents), name)?.span;
+
+    let mut updated = SecretString::with_capacity(contents.len());
+    updated.push_str(&contents[..span.start]);
+    updated.push_str(&contents[span.end..]);
+
+    Some(updated)
+}
+
+/// First field named `name`, names are not case sensitive
+fn find<'a>(lines: &[Line<'a>], name: &str) -> Option<Field<'a>> {
+    parse_fields(lines)
+        .into_iter()
+        .find(|field| field.name.eq_ignore_ascii_case(name))
+}
+
+/// Check if plain `value` would be read by YAML as something else than a string,
+/// like a number, a boolean or null
+///
+/// Values starting with a digit are numbers, dates or times for some YAML parsers
+fn is_yaml_typed(value: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "~", "null", "true", "false", "yes", "no", "on", "off", "y", "n", ".inf", ".nan",
+    ];
+
+    let unsigned = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let number = unsigned.strip_prefix('.').unwrap_or(unsigned);
+
+    KEYWORDS
+        .iter()
+        .any(|keyword| unsigned.eq_ignore_ascii_case(keyword))
+        || number.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Write `value` as a YAML scalar, quoted if it would not be read back as it is
+fn yaml_scalar(value: &str) -> SecretString {
+    let plain = !value.is_empty()
+        && !is_yaml_typed(value)
+        && value.trim() == value
+        && !value.starts_with(|c| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        && !value.ends_with(':')
+        && !value.contains(": ")
+        && !value.contains(" #")
+        && !value.contains(|c: char| c.is_control());
+    if plain {
+        return value.into();
+    }
+
+    // Only double quotes can hold line endings and tabulations
+    let mut quoted = SecretString::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// Split `name: value` line, `None` if it is not a field
+fn split_field(line: &str) -> Option<(&str, &str)> {
+    let separator = line.find(':')?;
+    let name = line[..separator].trim();
+    let value = &line[separator + 1..];
+
+    // Like in `https://example.com`, a value is separated from the name by a space
+    if name.is_empty() || !(value.is_empty() || value.starts_with(' ')) {
+        return None;
+    }
+
+    Some((name, value))
+}
+
+/// Read top level `name: value` pairs of a YAML mapping
+///
+/// Values can be plain, quoted, block scalars (`|`, `>`) or nested YAML, kept as text
+fn parse_yaml<'a>(lines: &[Line<'a>]) -> Vec<Field<'a>> {
+    let mut fields = Vec::new();
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = &lines[i];
+        i += 1;
+
+        // Comments, empty lines and indented lines which do not belong to a field
+        let text = line.text;
+        if text.trim().is_empty() || text.starts_with('#') || text.starts_with(' ') {
+            continue;
+        }
+        let (name, value) = match split_field(text) {
+            Some(field) => field,
+            None => continue,
+        };
+        let name = name.trim_matches(|c| c == '"' || c == '\'');
+        let value = value.trim();
+
+        // Lines of a block are indented, or empty
+        let block_start = i;
+        while i < lines.len() && (lines[i].text.starts_with(' ') || lines[i].text.trim().is_empty())
+        {
+            i += 1;
+        }
+
+        // Trailing empty lines do not belong to the value
+        let block_end = lines[block_start..i]
+            .iter()
+            .rposition(|line| !line.text.trim().is_empty())
+            .map_or(block_start, |end| block_start + end + 1);
+        let block = lines[block_start..block_end]
+            .iter()
+            .map(|line| line.text)
+            .collect::<Vec<_>>();
+
+        let value = if value.starts_with('|') || value.starts_with('>') {
+            block_scalar(value, &block)
+        } else if value.is_empty() || value.starts_with('#') {
+            // Nested YAML, or nothing
+            block_scalar("|-", &block)
+        } else if value.starts_with('\'') {
+            single_quoted(value)
+        } else if value.starts_with('"') {
+            double_quoted(value)
+        } else {
+            // Plain scalar ends with its comment
+            match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().into(),
+                None => value.into(),
+            }
+        };
+
+        let last = &lines[block_end - 1];
+        fields.push(Field {
+            name,
+            value,
+            span: line.span.start..last.span.end,
+            ending: last.ending,
+            yaml: true,
+        });
+    }
+
+    fields
+}
+
+/// Value of a block scalar: `|` keeps newlines, `>` folds lines with spaces
+///
+/// `-` removes the final newline
+fn block_scalar(indicator: &str, block: &[&str]) -> SecretString {
+    let folded = indicator.starts_with('>');
+    let strip = indicator.contains('-');
+
+    let indentation = block
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    let mut value = SecretString::with_capacity(block.iter().map(|line| line.len() + 1).sum());
+    for (i, line) in block.iter().enumerate() {
+        if i > 0 {
+            value.push(if folded { ' ' } else { '\n' });
+        }
+        value.push_str(line.get(indentation..).unwrap_or_default());
+    }
+    if !strip && !block.is_empty() {
+        value.push('\n');
+    }
+
+    value
+}
+
+/// Value between single quotes, where `''` is a quote
+fn single_quoted(value: &str) -> SecretString {
+    let inner = value[1..]
+        .rfind('\'')
+        .map_or(&value[1..], |end| &value[1..end + 1]);
+
+    let mut unquoted = SecretString::with_capacity(inner.len());
+    for (i, part) in inner.split("''").enumerate() {
+        if i > 0 {
+            unquoted.push('\'');
+        }
+        unquoted.push_str(part);
+    }
+
+    unquoted
+}
+
+/// Value between double quotes, with backslash escapes
+fn double_quoted(value: &str) -> SecretString {
+    let mut unquoted = SecretString::with_capacity(value.len());
+
+    let mut chars = value[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => unquoted.push('\n'),
+                Some('t') => unquoted.push('\t'),
+                Some('r') => unquoted.push('\r'),
+                Some(c) => unquoted.push(c),
+                None => break,
+            },
+            c => unquoted.push(c),
+        }
+    }
+
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn pass_fields() {
+        let fields = super::parse(
+            "my_password\nlogin: me\nurl: https://example.com\nsome notes\nhttps://example.com\n",
+        );
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].0, "login");
+        assert_eq!(fields[0].1, "me");
+        assert_eq!(fields[1].0, "url");
+        assert_eq!(fields[1].1, "https://example.com");
+
+        // First line is the password, even if it looks like a field
+        assert_eq!(super::parse("pin: 1234\n").is_empty(), true);
+    }
+
+    #[test]
+    fn yaml_fields() {
+        let fields = super::parse(
+            "my_password\n\
+             login: me\n\
+             ---\n\
+             # comment\n\
+             user: 'it''s me'\n\
+             token: \"a\\\"b\" # comment\n\
+             port: 8080 # comment\n\
+             notes: |\n  first line\n  second line\n\n\
+             recovery: >-\n  one\n  two\n\
+             nested:\n  key: value\n  other: value\n",
+        );
+        let names = fields.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["login", "user", "token", "port", "notes", "recovery", "nested"]
+        );
+        assert_eq!(fields[1].1, "it's me");
+        assert_eq!(fields[2].1, "a\"b");
+        assert_eq!(fields[3].1, "8080");
+        assert_eq!(fields[4].1, "first line\nsecond line\n");
+        assert_eq!(fields[5].1, "one two");
+        assert_eq!(fields[6].1, "key: value\nother: value");
+    }
+
+    #[test]
+    fn set_fields() -> anyhow::Result<()> {
+        let contents = "my_password\nlogin: me\nsome notes\n";
+        assert_eq!(
+            super::set(contents, "Login", "you")?,
+            "my_password\nlogin: you\nsome notes\n"
+        );
+        assert_eq!(
+            super::set(contents, "url", "https://example.com")?,
+            "my_password\nlogin: me\nsome notes\nurl: https://example.com\n"
+        );
+
+        // Lines endings are kept
+        assert_eq!(
+            super::set("pin\r\nlogin: me", "login", "you")?,
+            "pin\r\nlogin: you"
+        );
+        assert_eq!(super::set("pin", "login", "me")?, "pin\nlogin: me\n");
+
+        // Values are quoted in YAML
+        let contents = "my_password\n---\nnotes: |\n  first\n  second\n\nport: 8080\n";
+        let updated = super::set(contents, "notes", "it's: me\nagain")?;
+        assert_eq!(
+            updated,
+            "my_password\n---\nnotes: \"it's: me\\nagain\"\n\nport: 8080\n"
+        );
+        assert_eq!(super::parse(&updated)[0].1, "it's: me\nagain");
+        assert_eq!(
+            super::set(contents, "user", "me")?,
+            format!("{}user: me\n", contents).as_str()
+        );
+
+        assert_eq!(super::set(contents, "a: b", "c").is_err(), true);
+        assert_eq!(super::set("pin\n", "notes", "a\nb").is_err(), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn yaml_scalars() {
+        for value in &[
+            "me",
+            "https://example.com",
+            "it's",
+            "a-b",
+            "nothing",
+            "password1",
+        ] {
+            assert_eq!(super::yaml_scalar(value), *value);
+        }
+
+        // Numbers, booleans, null, dates and times are strings once quoted
+        for value in &[
+            "0123",
+            "1e3",
+            "0x1F",
+            "1_000",
+            ".5",
+            "+1",
+            "-1",
+            "12:30",
+            "2020-01-01",
+            "yes",
+            "No",
+            "ON",
+            "off",
+            "y",
+            "null",
+            "~",
+            "true",
+            "False",
+            ".inf",
+            "-.Inf",
+            ".NaN",
+            "",
+        ] {
+            assert_eq!(super::yaml_scalar(value), format!("\"{}\"", value).as_str());
+        }
+    }
+
+    #[test]
+    fn unset_fields() {
+        let contents = "my_password\nlogin: me\nsome notes\n---\nnotes: |\n  first\n\nport: 8080\n";
+        assert_eq!(
+            super::unset(contents, "login").unwrap(),
+            "my_password\nsome notes\n---\nnotes: |\n  first\n\nport: 8080\n"
+        );
+        assert_eq!(
+            super::unset(contents, "notes").unwrap(),
+            "my_password\nlogin: me\nsome notes\n---\n\nport: 8080\n"
+        );
+        assert_eq!(super::unset(contents, "url").is_none(), true);
+    }
+}
@@ -0,0 +1,422 @@
+use crate::constants::PASSWORD_LENGTH;
+use crate::secret::SecretString;
+use anyhow::{anyhow, Context};
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~ ";
+
+/// Characters easy to mistake for each other, never used unless they are allowed explicitly
+const SIMILAR: &str = "iIlL1oO0\"'`|";
+
+/// Words of passphrases, the EFF large wordlist
+///
+/// A word is the last one of its line, after the dice rolls which pick it like `11111 abacus`
+const WORDLIST: &str = include_str!("wordlist.txt");
+
+/// How to generate a random password
+#[derive(Clone, Debug)]
+pub enum Generator {
+    Characters(Characters),
+    Words(Words),
+}
+
+impl Generator {
+    pub fn generate(&self) -> anyhow::Result<SecretString> {
+        match self {
+            Generator::Characters(characters) => characters.generate(),
+            Generator::Words(words) => words.generate(),
+        }
+    }
+
+    /// Bits of entropy of the generated passwords, when the way they are generated is known
+    ///
+    /// It is only an upper bound if the passwords are not picked uniformly, see `is_uniform`
+    pub fn entropy(&self) -> f64 {
+        match self {
+            Generator::Characters(characters) => characters.entropy(),
+            Generator::Words(words) => words.entropy(),
+        }
+    }
+
+    /// Check if every password `generate` can give is as likely as the others
+    pub fn is_uniform(&self) -> bool {
+        match self {
+            // Required characters of each class are picked apart from the others
+            Generator::Characters(characters) => characters.min_per_class == 0,
+            Generator::Words(_) => true,
+        }
+    }
+}
+
+/// Password of random characters
+#[derive(Clone, Debug)]
+pub struct Characters {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    /// Symbols include the space
+    pub symbols: bool,
+    /// Characters to use instead of the classes above
+    pub allowed: Option<String>,
+    /// Characters never used
+    pub excluded: String,
+    /// Minimum number of characters of each class in the password
+    pub min_per_class: usize,
+}
+
+impl Default for Characters {
+    fn default() -> Self {
+        Self {
+            length: PASSWORD_LENGTH,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            allowed: None,
+            excluded: String::new(),
+            min_per_class: 0,
+        }
+    }
+}
+
+impl Characters {
+    /// Characters to use, grouped by class
+    ///
+    /// Allowed characters are grouped in the same classes: lowercase, uppercase, digits, others
+    fn classes(&self) -> Vec<Vec<char>> {
+        let classes = match &self.allowed {
+            Some(allowed) => {
+                let mut allowed = allowed.chars().collect::<Vec<_>>();
+                allowed.sort_unstable();
+                allowed.dedup();
+
+                let (lowercase, others): (Vec<_>, Vec<_>) =
+                    allowed.into_iter().partition(char::is_ascii_lowercase);
+                let (uppercase, others): (Vec<_>, Vec<_>) =
+                    others.into_iter().partition(char::is_ascii_uppercase);
+                let (digits, symbols): (Vec<_>, Vec<_>) =
+                    others.into_iter().partition(char::is_ascii_digit);
+
+                vec![lowercase, uppercase, digits, symbols]
+            }
+            None => [
+                (self.lowercase, LOWERCASE),
+                (self.uppercase, UPPERCASE),
+                (self.digits, DIGITS),
+                (self.symbols, SYMBOLS),
+            ]
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, class)| class.chars().filter(|c| !SIMILAR.contains(*c)).collect())
+            .collect(),
+        };
+
+        classes
+            .into_iter()
+            .map(|class| {
+                class
+                    .into_iter()
+                    .filter(|c| !self.excluded.contains(*c))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|class| !class.is_empty())
+            .collect()
+    }
+
+    /// Generate a password of `length` random characters, `min_per_class` of each class at least
+    pub fn generate(&self) -> anyhow::Result<SecretString> {
+        let classes = self.classes();
+        if classes.is_empty() {
+            return Err(anyhow!(
+                "There are no characters to generate a password from"
+            ));
+        }
+        if self.length == 0 {
+            return Err(anyhow!("Length of password has to be at least 1"));
+        }
+        if self.min_per_class * classes.len() > self.length {
+            return Err(anyhow!(
+                "A password of {} characters can not have {} characters of each of its {} classes",
+                self.length,
+                self.min_per_class,
+                classes.len()
+            ));
+        }
+
+        let mut rng = OsRng;
+        let all = classes.concat();
+
+        // Characters required in each class first, then any character, in a random order
+        let mut chars = Vec::with_capacity(self.length);
+        for class in &classes {
+            for _ in 0..self.min_per_class {
+                chars.extend(class.choose(&mut rng));
+            }
+        }
+        while chars.len() < self.length {
+            chars.extend(all.choose(&mut rng));
+        }
+        chars.shuffle(&mut rng);
+
+        let mut password = SecretString::with_capacity(chars.len() * 4);
+        for c in &chars {
+            password.push(*c);
+        }
+        unsafe { memsec::memzero(chars.as_mut_ptr() as *mut u8, chars.len() * 4) };
+
+        Ok(password)
+    }
+
+    /// Bits of entropy of a password picked among all the ones with `min_per_class`
+    /// characters of each class
+    ///
+    /// Counts of passwords are kept as logarithms, they do not fit in a float
+    fn entropy(&self) -> f64 {
+        let log2_factorials = (0..=self.length)
+            .scan(0f64, |sum, n| {
+                if n > 0 {
+                    *sum += (n as f64).log2();
+                }
+                Some(*sum)
+            })
+            .collect::<Vec<_>>();
+
+        // Passwords of `n` characters of the classes seen so far, for each `n`,
+        // without telling apart orders of characters of different classes
+        let mut log2_counts = vec![f64::NEG_INFINITY; self.length + 1];
+        log2_counts[0] = 0.0;
+        for class in self.classes() {
+            let log2_size = (class.len() as f64).log2();
+            let mut next = vec![f64::NEG_INFINITY; self.length + 1];
+
+            for (used, log2_count) in log2_counts.iter().enumerate() {
+                if *log2_count == f64::NEG_INFINITY {
+                    continue;
+                }
+
+                // `n` characters of this class, in any of the ways to pick them
+                for n in self.min_per_class..=(self.length - used) {
+                    let log2_ways = log2_count + n as f64 * log2_size - log2_factorials[n];
+                    next[used + n] = log2_add(next[used + n], log2_ways);
+                }
+            }
+
+            log2_counts = next;
+        }
+
+        // Characters of the different classes in any order
+        log2_counts[self.length] + log2_factorials[self.length]
+    }
+}
+
+/// Base 2 logarithm of `2^a + 2^b`
+fn log2_add(a: f64, b: f64) -> f64 {
+    let (high, low) = if a > b { (a, b) } else { (b, a) };
+    if low == f64::NEG_INFINITY {
+        return high;
+    }
+
+    high + (1.0 + (low - high).exp2()).log2()
+}
+
+/// Passphrase of random words, easier to type than random characters
+#[derive(Clone, Debug)]
+pub struct Words {
+    pub count: usize,
+    pub separator: String,
+    /// First letter of each word is uppercase
+    pub capitalize: bool,
+    /// A random digit is added after one of the words
+    pub digit: bool,
+}
+
+impl Words {
+    fn wordlist() -> Vec<&'static str> {
+        WORDLIST
+            .lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .collect()
+    }
+
+    pub fn generate(&self) -> anyhow::Result<SecretString> {
+        if self.count == 0 {
+            return Err(anyhow!("Passphrase has to be at least 1 word"));
+        }
+
+        let wordlist = Self::wordlist();
+        let mut rng = OsRng;
+        let digit_word = rng.gen_range(0..self.count);
+
+        let mut passphrase = SecretString::with_capacity(self.count * (10 + self.separator.len()));
+        for i in 0..self.count {
+            if i > 0 {
+                passphrase.push_str(&self.separator);
+            }
+
+            let word = wordlist.choose(&mut rng).context("Wordlist is empty")?;
+            match word.chars().next() {
+                Some(first) if self.capitalize => {
+                    for c in first.to_uppercase() {
+                        passphrase.push(c);
+                    }
+                    passphrase.push_str(&word[first.len_utf8()..]);
+                }
+                _ => passphrase.push_str(word),
+            }
+
+            if self.digit && i == digit_word {
+                if let Some(digit) = std::char::from_digit(rng.gen_range(0..10), 10) {
+                    passphrase.push(digit);
+                }
+            }
+        }
+
+        Ok(passphrase)
+    }
+
+    fn entropy(&self) -> f64 {
+        let mut entropy = self.count as f64 * (Self::wordlist().len() as f64).log2();
+
+        // Which digit, and after which word
+        if self.digit {
+            entropy += 10f64.log2() + (self.count as f64).log2();
+        }
+
+        entropy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Characters, Words};
+
+    #[test]
+    fn default() -> anyhow::Result<()> {
+        let password = Characters::default().generate()?;
+        assert_eq!(password.chars().count(), 20);
+        assert_eq!(password.contains(|c| super::SIMILAR.contains(c)), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn classes() -> anyhow::Result<()> {
+        let generator = Characters {
+            length: 32,
+            symbols: false,
+            uppercase: false,
+            min_per_class: 10,
+            excluded: "abc".to_string(),
+            ..Default::default()
+        };
+        let password = generator.generate()?;
+        assert_eq!(password.len(), 32);
+        assert_eq!(password.chars().all(|c| c.is_ascii_alphanumeric()), true);
+        assert_eq!(password.contains(|c: char| c.is_ascii_uppercase()), false);
+        assert_eq!(password.contains(|c| "abc".contains(c)), false);
+        assert_eq!(
+            password.chars().filter(char::is_ascii_digit).count() >= 10,
+            true
+        );
+
+        let pin = Characters {
+            length: 6,
+            allowed: Some("0123456789".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(pin.generate()?.chars().all(|c| c.is_ascii_digit()), true);
+
+        let impossible = Characters {
+            length: 4,
+            min_per_class: 2,
+            ..Default::default()
+        };
+        assert_eq!(impossible.generate().is_err(), true);
+
+        // 256 passwords of 4 characters, 16 of them only letters and 16 only digits
+        let constrained = Characters {
+            length: 4,
+            allowed: Some("ab01".to_string()),
+            min_per_class: 1,
+            ..Default::default()
+        };
+        assert_eq!((constrained.entropy().exp2()).round(), 224.0);
+        assert_eq!(
+            Characters {
+                min_per_class: 0,
+                ..constrained.clone()
+            }
+            .entropy()
+            .round(),
+            8.0
+        );
+        assert_eq!(
+            super::Generator::Characters(constrained.clone()).is_uniform(),
+            false
+        );
+
+        // Long passwords nearly always have a character of each class, and their
+        // counts do not fit in a float
+        let long = Characters {
+            length: 200,
+            ..Default::default()
+        };
+        let with_minimum = Characters {
+            min_per_class: 1,
+            ..long.clone()
+        };
+        assert_eq!(with_minimum.entropy() < long.entropy(), true);
+        assert_eq!(with_minimum.entropy() > long.entropy() - 1.0, true);
+
+        let nothing = Characters {
+            allowed: Some("ab".to_string()),
+            excluded: "ab".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(nothing.generate().is_err(), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn words() -> anyhow::Result<()> {
+        let mut wordlist = Words::wordlist();
+        assert_eq!(wordlist.len(), 7776);
+        wordlist.sort_unstable();
+        wordlist.dedup();
+        assert_eq!(wordlist.len(), 7776);
+
+        let words = Words {
+            count: 6,
+            separator: ".".to_string(),
+            capitalize: true,
+            digit: true,
+        };
+        let passphrase = words.generate()?;
+        let parts = passphrase.split('.').collect::<Vec<_>>();
+        assert_eq!(parts.len(), 6);
+        assert_eq!(
+            parts
+                .iter()
+                .all(|word| word.starts_with(|c: char| c.is_uppercase())),
+            true
+        );
+        assert_eq!(passphrase.chars().filter(char::is_ascii_digit).count(), 1);
+
+        // 6 words of 12.9 bits
+        let entropy = Words {
+            digit: false,
+            ..words
+        }
+        .entropy();
+        assert_eq!(entropy.round(), 78.0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use crate::constants::PUBLIC_KEYS_FOLDER;
+use crate::recipients::{find_cert, load_certs};
+use crate::settings::is_key_file;
+use anyhow::Context;
+use sequoia_openpgp::parse::Parse;
+use sequoia_openpgp::serialize::Serialize;
+use sequoia_openpgp::Cert;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Folder of the public keyring, inside the password store
+pub fn keyring_path(store_path: &Path) -> PathBuf {
+    store_path.join(PUBLIC_KEYS_FOLDER)
+}
+
+/// Path of a certificate in the keyring
+///
+/// Example: `.public-keys/AF6DF58D346543BD06CFFE9922A2542A924C5648.asc`
+fn cert_path(store_path: &Path, cert: &Cert) -> PathBuf {
+    keyring_path(store_path).join(format!("{:X}.asc", cert.fingerprint()))
+}
+
+/// Load all certificates of the public keyring
+///
+/// Only files with a key extension (like `.asc`) are read, others are ignored.
+/// Returns an empty list if there is no keyring in the password store
+pub fn load_keyring(store_path: &Path) -> anyhow::Result<Vec<Cert>> {
+    let mut certs = Vec::new();
+
+    let path = keyring_path(store_path);
+    if !path.is_dir() {
+        return Ok(certs);
+    }
+
+    for entry in std::fs::read_dir(&path)? {
+        let entry_path = entry?.path();
+        if entry_path.is_file() && is_key_file(&entry_path) {
+            certs.append(&mut load_certs(&entry_path)?);
+        }
+    }
+
+    Ok(certs)
+}
+
+/// Load certificates of all the files `keys`
+pub fn load_keys(keys: &[PathBuf]) -> anyhow::Result<Vec<Cert>> {
+    let mut certs = Vec::new();
+    for key in keys {
+        certs.append(&mut load_certs(key)?);
+    }
+
+    Ok(certs)
+}
+
+/// Load certificates that can be used as recipients:
+/// the ones in the files `keys` followed by the ones of the public keyring
+pub fn load_known_certs(store_path: &Path, keys: &[PathBuf]) -> anyhow::Result<Vec<Cert>> {
+    let mut certs = load_keys(keys)?;
+    certs.append(&mut load_keyring(store_path)?);
+
+    Ok(certs)
+}
+
+/// Add public parts of `certs` to the keyring
+///
+/// If a certificate is already in the keyring, both versions are merged
+///
+/// Returns the paths of the written files
+pub fn import(store_path: &Path, certs: Vec<Cert>) -> anyhow::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(keyring_path(store_path))
+        .context("Failed to create folder for public keys")?;
+
+    let mut paths = Vec::new();
+    for cert in certs {
+        // Never store secret keys in the password store
+        let mut cert = cert.strip_secret_key_material();
+
+        let path = cert_path(store_path, &cert);
+        if path.is_file() {
+            let existing = Cert::from_file(&path)
+                .with_context(|| format!("Failed to load key from file {}", path.display()))?;
+            cert = existing.merge_public_and_secret(cert)?;
+        }
+
+        let mut file = File::create(&path)?;
+        cert.armored().serialize(&mut file)?;
+
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Find the certificate designated by `key` (fingerprint, long key id or email) in the keyring
+pub fn find(store_path: &Path, key: &str) -> anyhow::Result<Cert> {
+    let keyring = load_keyring(store_path)?;
+
+    find_cert(&keyring, key)?
+        .cloned()
+        .with_context(|| format!("Could not find key `{}` in the keyring", key))
+}
+
+/// Remove a certificate from the keyring
+///
+/// Returns the path of the deleted file
+pub fn remove(store_path: &Path, cert: &Cert) -> anyhow::Result<PathBuf> {
+    let path = cert_path(store_path, cert);
+    std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recipients::load_certs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn import_remove() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let certs = load_certs(secret_key_path.as_ref())?;
+
+        // Empty keyring
+        assert_eq!(super::load_keyring(tmp_dir.path())?.len(), 0);
+
+        // Import the same key twice, it should be merged
+        super::import(tmp_dir.path(), certs.clone())?;
+        let paths = super::import(tmp_dir.path(), certs)?;
+        assert_eq!(paths.len(), 1);
+
+        // Files which are not keys are ignored
+        std::fs::write(
+            super::keyring_path(tmp_dir.path()).join("README.md"),
+            "Keys",
+        )?;
+
+        // Only public parts are kept
+        let keyring = super::load_keyring(tmp_dir.path())?;
+        assert_eq!(keyring.len(), 1);
+        assert_eq!(keyring[0].is_tsk(), false);
+
+        // Find and remove key
+        assert_eq!(
+            super::find(tmp_dir.path(), "nobody@example.com").is_err(),
+            true
+        );
+        let cert = super::find(tmp_dir.path(), "phil@example.com")?;
+        super::remove(tmp_dir.path(), &cert)?;
+        assert_eq!(super::load_keyring(tmp_dir.path())?.len(), 0);
+
+        Ok(())
+    }
+}
@@ -1,12 +1,27 @@
+// Tests compare booleans with `assert_eq!` on purpose
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
+mod agent;
 pub mod cmd;
 mod constants;
 mod decrypt;
 mod encrypt;
+mod fields;
+mod generator;
+mod keyring;
+mod passphrase;
 mod password;
+mod policy;
+mod prompt;
+mod recipients;
+mod secret;
 pub mod settings;
+mod sign;
 mod sync;
 mod tree;
 
+use crate::constants::PASSPHRASE_SEPARATOR;
+use crate::generator::Words;
 use crate::settings::Settings;
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -37,9 +52,22 @@ enum Command {
     Init {
         /// Location of PGP key to use
         pgp_key: PathBuf,
-        #[structopt(short = "p", long = "path")]
+        /// Recipients to encrypt passwords for (fingerprints, key ids or emails)
+        ///
+        /// Passwords already in the store are re-encrypted for the new recipients
+        recipients: Vec<String>,
+        #[structopt(long = "store")]
         /// Location of password store
-        path: Option<PathBuf>,
+        store: Option<PathBuf>,
+        /// Set recipients of a subfolder of the password store, like `pass init -p`
+        #[structopt(short = "p", long = "path", alias = "subfolder")]
+        subfolder: Option<String>,
+        /// Generate a new key for this user id (like `Name <email>`), written to the location of PGP key
+        #[structopt(long = "generate-key")]
+        generate_key: Option<String>,
+        /// Location to write the public key of the generated key
+        #[structopt(long = "public-key", requires = "generate-key")]
+        public_key: Option<PathBuf>,
     },
     #[structopt(name = "ls")]
     /// List passwords
@@ -56,6 +84,29 @@ enum Command {
         /// Display password as a qr-code
         #[structopt(short = "q", long = "qrcode")]
         qr_code: bool,
+        /// Write password to a file as it is, for entries which are files
+        #[structopt(
+            short = "o",
+            long = "output",
+            conflicts_with_all = &["line", "clipboard", "qr-code"]
+        )]
+        output: Option<PathBuf>,
+        /// Overwrite the file of `--output` if it exists
+        #[structopt(long = "force", requires = "output")]
+        force: bool,
+        /// Display only a field of password, like `login` for a `login: me` line
+        #[structopt(
+            short = "f",
+            long = "field",
+            conflicts_with_all = &["line", "output"]
+        )]
+        field: Option<String>,
+        /// List names of the fields of password
+        #[structopt(
+            long = "fields",
+            conflicts_with_all = &["line", "clipboard", "qr-code", "output", "field"]
+        )]
+        fields: bool,
     },
     /// Insert new password
     Insert {
@@ -69,20 +120,116 @@ enum Command {
         /// Force insertion of password
         #[structopt(short = "f", long = "force")]
         force: bool,
+        /// Protect password with a passphrase only, no key is needed to decrypt it
+        #[structopt(long = "symmetric")]
+        symmetric: bool,
+        /// Insert contents of a file as they are, instead of a password (keyfile, document...)
+        #[structopt(long = "file", conflicts_with_all = &["multi-line", "echo"])]
+        file: Option<PathBuf>,
+        /// Keep the password even if it does not follow the policy of its folder
+        #[structopt(long = "ignore-policy")]
+        ignore_policy: bool,
     },
     /// Edit existing password
-    Edit { password: String },
+    Edit {
+        password: String,
+        /// Keep the password even if it does not follow the policy of its folder
+        #[structopt(long = "ignore-policy")]
+        ignore_policy: bool,
+    },
+    /// Add lines at the end of existing password, read from stdin if there is no `--line`
+    Append {
+        password: String,
+        /// Line to add, can be given several times
+        #[structopt(short = "l", long = "line", number_of_values = 1)]
+        lines: Vec<String>,
+    },
+    /// Set fields of existing password, like `login=me`, the other lines are kept
+    Set {
+        password: String,
+        /// Fields to set, as `name=value`
+        #[structopt(required = true)]
+        fields: Vec<String>,
+    },
+    /// Remove fields of existing password
+    Unset {
+        password: String,
+        /// Names of the fields to remove
+        #[structopt(required = true)]
+        fields: Vec<String>,
+    },
     /// Generate new password
     Generate {
         password: String,
-        /// Default length is 8
+        /// Default length is 20
         length: Option<usize>,
+        /// Replace only the first line of the existing password, its other lines are kept
+        #[structopt(
+            short = "i",
+            long = "in-place",
+            conflicts_with_all = &["force", "symmetric"]
+        )]
+        in_place: bool,
         /// Force insertion of password
         #[structopt(short = "f", long = "force")]
         force: bool,
         /// Copy password to clipboard
         #[structopt(short = "c", long = "clipboard")]
         clipboard: bool,
+        /// Protect password with a passphrase only, no key is needed to decrypt it
+        #[structopt(long = "symmetric")]
+        symmetric: bool,
+        /// Do not use symbols, nor spaces
+        #[structopt(short = "n", long = "no-symbols")]
+        no_symbols: bool,
+        /// Do not use digits
+        #[structopt(long = "no-digits")]
+        no_digits: bool,
+        /// Do not use uppercase letters
+        #[structopt(long = "no-uppercase")]
+        no_uppercase: bool,
+        /// Use only these characters, instead of letters, digits and symbols
+        #[structopt(
+            long = "allowed",
+            conflicts_with_all = &["no-symbols", "no-digits", "no-uppercase"]
+        )]
+        allowed: Option<String>,
+        /// Never use these characters
+        #[structopt(long = "exclude")]
+        exclude: Option<String>,
+        /// Minimum number of lowercase letters, uppercase letters, digits and symbols
+        #[structopt(long = "min-per-class")]
+        min_per_class: Option<usize>,
+        /// Generate a passphrase of this number of random words, easier to type
+        ///
+        /// The policy of the folder does not change the words, the passphrase is only checked
+        /// against it
+        #[structopt(
+            short = "w",
+            long = "words",
+            conflicts_with_all = &[
+                "length",
+                "no-symbols",
+                "no-digits",
+                "no-uppercase",
+                "allowed",
+                "exclude",
+                "min-per-class",
+            ]
+        )]
+        words: Option<usize>,
+        /// Put this between the words of the passphrase, default is a space
+        #[structopt(long = "separator", requires = "words")]
+        separator: Option<String>,
+        /// Start each word of the passphrase with an uppercase letter
+        #[structopt(long = "capitalize", requires = "words")]
+        capitalize: bool,
+        /// Add a random digit after one of the words of the passphrase
+        #[structopt(long = "digit", requires = "words")]
+        digit: bool,
+        /// Keep the password even if it does not follow the policy of its folder
+        #[structopt(long = "ignore-policy")]
+        ignore_policy: bool,
     },
     #[structopt(name = "rm")]
     /// Delete existing password or directory
@@ -100,6 +247,12 @@ enum Command {
     Git(GitCommands),
     /// OTP commands
     Otp(OtpCommands),
+    /// Manage keys of the password store
+    Key(KeyCommands),
+    /// Keep the unlocked key in memory, to enter its password once per session
+    Agent(AgentCommands),
+    /// Make the agent forget the unlocked keys
+    Lock,
     /// Dump current settings
     Settings,
 }
@@ -110,6 +263,52 @@ enum GitCommands {
     Init,
 }
 
+#[derive(StructOpt)]
+enum KeyCommands {
+    /// Import public keys from a file to the keyring of the password store
+    Import {
+        /// Location of file containing public keys
+        path: PathBuf,
+    },
+    /// List public keys of the keyring of the password store
+    List,
+    /// Remove public key from the keyring of the password store
+    Remove {
+        /// Fingerprint, key id or email of the key
+        key: String,
+    },
+    /// Show fingerprint, subkeys, expiration and revocation of the key of the password store
+    Info,
+    /// Export the public key of the password store
+    ExportPublic {
+        /// Location of file to write, the key is printed if there is none
+        path: Option<PathBuf>,
+    },
+    /// Change password of the secret key of the password store
+    Passwd,
+    /// Sign the `.pass-policy` file of the password store, needed when signing keys are set
+    SignPolicy {
+        /// Sign the policy of a subfolder of the password store, like `init -p`
+        #[structopt(short = "p", long = "path", alias = "subfolder")]
+        subfolder: Option<String>,
+    },
+}
+
+#[derive(StructOpt)]
+enum AgentCommands {
+    /// Start agent in the background
+    Start,
+    /// Stop agent
+    Stop,
+    /// Run agent in the foreground
+    #[structopt(setting = structopt::clap::AppSettings::Hidden)]
+    Serve {
+        /// Seconds before an unused key is forgotten
+        #[structopt(long = "timeout")]
+        timeout: u64,
+    },
+}
+
 #[derive(StructOpt)]
 enum OtpCommands {
     /// Get 2fa code
@@ -122,17 +321,23 @@ enum OtpCommands {
 }
 
 fn main() -> anyhow::Result<()> {
+    // Secrets of the process must not end up in a crash dump
+    secret::disable_core_dumps();
+
     let cli_args = CliArgs::from_args();
 
     let mut settings = Settings::try_load();
+    prompt::use_pinentry(settings.get_pinentry());
 
     // If a password is passed, pass it to show command
     if let Some(password) = cli_args.password {
         return cmd::show(
             Some(password),
-            cli_args.line,
+            cmd::show::Part::new(cli_args.line, None, false),
             cli_args.clipboard,
             cli_args.qr_code,
+            None,
+            false,
             &settings,
         );
     }
@@ -140,27 +345,104 @@ fn main() -> anyhow::Result<()> {
     // Run command
     match cli_args.cmd {
         Some(cmd) => match cmd {
-            Command::Init { pgp_key, path } => cmd::init(&pgp_key, path, &mut settings)?,
+            Command::Init {
+                pgp_key,
+                recipients,
+                store,
+                subfolder,
+                generate_key,
+                public_key,
+            } => cmd::init(
+                &pgp_key,
+                store,
+                &recipients,
+                subfolder,
+                generate_key.as_deref(),
+                public_key.as_deref(),
+                &mut settings,
+            )?,
             Command::List { password } => cmd::list(password, &settings)?,
             Command::Show {
                 password,
                 line,
                 clipboard,
                 qr_code,
-            } => cmd::show(password, line, clipboard, qr_code, &settings)?,
+                output,
+                force,
+                field,
+                fields,
+            } => cmd::show(
+                password,
+                cmd::show::Part::new(line, field.as_deref(), fields),
+                clipboard,
+                qr_code,
+                output.as_deref(),
+                force,
+                &settings,
+            )?,
             Command::Insert {
                 password,
                 multi_line,
                 echo,
                 force,
-            } => cmd::insert(&password, multi_line, echo, force, &settings)?,
-            Command::Edit { password } => cmd::edit(&password, &settings)?,
+                symmetric,
+                file,
+                ignore_policy,
+            } => cmd::insert(
+                &password,
+                cmd::insert::Input::new(multi_line, file.as_deref()),
+                echo,
+                force,
+                symmetric,
+                ignore_policy,
+                &settings,
+            )?,
+            Command::Edit {
+                password,
+                ignore_policy,
+            } => cmd::edit(&password, ignore_policy, &settings)?,
+            Command::Append { password, lines } => cmd::append(&password, &lines, &settings)?,
+            Command::Set { password, fields } => cmd::set(&password, &fields, &settings)?,
+            Command::Unset { password, fields } => cmd::unset(&password, &fields, &settings)?,
             Command::Generate {
                 password,
                 length,
+                in_place,
                 force,
                 clipboard,
-            } => cmd::generate(&password, length, force, clipboard, &settings)?,
+                symmetric,
+                no_symbols,
+                no_digits,
+                no_uppercase,
+                allowed,
+                exclude,
+                min_per_class,
+                words,
+                separator,
+                capitalize,
+                digit,
+                ignore_policy,
+            } => {
+                let options = cmd::generate::Options {
+                    length,
+                    no_symbols,
+                    no_digits,
+                    no_uppercase,
+                    allowed,
+                    exclude,
+                    min_per_class,
+                    words: words.map(|count| Words {
+                        count,
+                        separator: separator.unwrap_or_else(|| PASSPHRASE_SEPARATOR.to_string()),
+                        capitalize,
+                        digit,
+                    }),
+                    ignore_policy,
+                };
+                cmd::generate(
+                    &password, options, in_place, force, clipboard, symmetric, &settings,
+                )?
+            }
             Command::Remove { path } => cmd::remove(&path, &settings)?,
             Command::Move {
                 old_path,
@@ -176,6 +458,25 @@ fn main() -> anyhow::Result<()> {
                     clipboard,
                 } => cmd::otp::code(&password, clipboard, &settings)?,
             },
+            Command::Key(key_cmd) => match key_cmd {
+                KeyCommands::Import { path } => cmd::key::import(&path, &settings)?,
+                KeyCommands::List => cmd::key::list(&settings)?,
+                KeyCommands::Remove { key } => cmd::key::remove(&key, &settings)?,
+                KeyCommands::Info => cmd::key::info(&settings)?,
+                KeyCommands::ExportPublic { path } => {
+                    cmd::key::export_public(path.as_deref(), &settings)?
+                }
+                KeyCommands::Passwd => cmd::key::passwd(&settings)?,
+                KeyCommands::SignPolicy { subfolder } => {
+                    cmd::key::sign_policy(subfolder.as_deref(), &settings)?
+                }
+            },
+            Command::Agent(agent_cmd) => match agent_cmd {
+                AgentCommands::Start => cmd::agent::start(&settings)?,
+                AgentCommands::Stop => cmd::agent::stop()?,
+                AgentCommands::Serve { timeout } => cmd::agent::serve(timeout)?,
+            },
+            Command::Lock => cmd::agent::lock()?,
             Command::Settings => settings.dump()?,
         },
         None => cmd::list(None, &settings)?,
@@ -0,0 +1,246 @@
+use crate::constants::{KEY_PASSWORD_ENV, KEY_PASSWORD_FD_ENV};
+use crate::prompt;
+use crate::secret::{read_to_secret, SecretString};
+use crate::settings::Settings;
+use anyhow::{anyhow, Context};
+use std::cell::RefCell;
+use std::fs::File;
+use std::mem::ManuallyDrop;
+use std::process::{Command, Stdio};
+
+thread_local! {
+    // A file descriptor can only be read once, keep what was read
+    static FD_PASSWORD: RefCell<Option<SecretString>> = const { RefCell::new(None) };
+}
+
+/// Keep first line of `input`, without its line ending
+fn first_line(input: &str) -> SecretString {
+    input.lines().next().unwrap_or_default().into()
+}
+
+/// Open file descriptor `fd` given by the user, it stays open when the file is dropped
+#[cfg(windows)]
+fn open_fd(fd: i32) -> anyhow::Result<ManuallyDrop<File>> {
+    use std::os::windows::io::{FromRawHandle, RawHandle};
+
+    extern "C" {
+        // File descriptors belong to the C runtime, which has a handle for each one
+        fn _get_osfhandle(fd: i32) -> isize;
+    }
+
+    let handle = unsafe { _get_osfhandle(fd) };
+    if handle == -1 || handle == -2 {
+        return Err(anyhow!("{} is not an open file descriptor", fd));
+    }
+
+    Ok(ManuallyDrop::new(unsafe {
+        File::from_raw_handle(handle as RawHandle)
+    }))
+}
+
+/// Open file descriptor `fd` given by the user, it stays open when the file is dropped
+#[cfg(not(windows))]
+fn open_fd(fd: i32) -> anyhow::Result<ManuallyDrop<File>> {
+    use std::os::unix::io::FromRawFd;
+
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(anyhow!("{} is not an open file descriptor", fd));
+    }
+
+    Ok(ManuallyDrop::new(unsafe { File::from_raw_fd(fd) }))
+}
+
+/// Read password from the file descriptor `fd`, the value of `KEY_PASSWORD_FD_ENV`
+fn from_fd(fd: Option<String>) -> anyhow::Result<Option<SecretString>> {
+    if let Some(password) = FD_PASSWORD.with(|cache| cache.borrow().clone()) {
+        return Ok(Some(password));
+    }
+
+    let fd = match fd {
+        Some(fd) => fd
+            .parse::<i32>()
+            .with_context(|| format!("{} is not a file descriptor", KEY_PASSWORD_FD_ENV))?,
+        None => return Ok(None),
+    };
+
+    // Standard input and outputs are used by the commands
+    if fd <= 2 {
+        return Err(anyhow!(
+            "{} can not be standard input or output, use another file descriptor",
+            KEY_PASSWORD_FD_ENV
+        ));
+    }
+
+    let input = read_to_secret(&mut *open_fd(fd)?)
+        .with_context(|| format!("Failed to read password from file descriptor {}", fd))?;
+    let password = first_line(&input);
+
+    FD_PASSWORD.with(|cache| *cache.borrow_mut() = Some(password.clone()));
+
+    Ok(Some(password))
+}
+
+/// Run `command` in a shell and keep the first line it prints
+fn from_command(command: &str) -> anyhow::Result<SecretString> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    let output = shell
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run passphrase command `{}`", command))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Passphrase command `{}` failed ({})",
+            command,
+            output.status
+        ));
+    }
+
+    Ok(first_line(&SecretString::from_utf8(output.stdout)?))
+}
+
+/// Get password of the secret key without a terminal, from the first of these sources:
+///
+/// 1. environment variable `PASS4THEWIN_KEY_PASSWORD`
+/// 2. file descriptor whose number is in environment variable `PASS4THEWIN_KEY_PASSWORD_FD`
+/// 3. output of `passphrase_command` of the settings
+///
+/// Returns `None` if there is no source
+pub fn from_sources(settings: &Settings) -> anyhow::Result<Option<SecretString>> {
+    from_sources_with(settings, |name| std::env::var(name).ok())
+}
+
+/// Like `from_sources`, with environment variables from `env`
+fn from_sources_with(
+    settings: &Settings,
+    env: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<Option<SecretString>> {
+    if let Some(password) = env(KEY_PASSWORD_ENV) {
+        return Ok(Some(password.into()));
+    }
+
+    if let Some(password) = from_fd(env(KEY_PASSWORD_FD_ENV))? {
+        return Ok(Some(password));
+    }
+
+    match settings.get_passphrase_command() {
+        Some(command) => Ok(Some(from_command(command)?)),
+        None => Ok(None),
+    }
+}
+
+/// Get password of the secret key from the sources of `from_sources`, or ask for it
+pub fn key_password(settings: &Settings) -> anyhow::Result<SecretString> {
+    match from_sources(settings)? {
+        Some(password) => Ok(password),
+        None => prompt::password("Enter password to decrypt key", "Password", None),
+    }
+}
+
+/// Get password of the key to sign from the sources of `from_sources`, or ask for it
+pub fn signing_key_password(settings: &Settings) -> anyhow::Result<SecretString> {
+    match from_sources(settings)? {
+        Some(password) => Ok(password),
+        None => prompt::password("Enter password to unlock signing key", "Password", None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::KEY_PASSWORD_ENV;
+    use crate::settings::Settings;
+
+    #[test]
+    fn passphrase_command() -> anyhow::Result<()> {
+        let mut settings = Settings::default();
+        assert_eq!(super::from_command("echo password")?, "password");
+
+        settings.set_passphrase_command("exit 1");
+        assert_eq!(super::from_sources(&settings).is_err(), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn environment_variable() -> anyhow::Result<()> {
+        let mut settings = Settings::default();
+        settings.set_passphrase_command("echo other");
+
+        // Environment variable comes first
+        let env = |name: &str| match name {
+            KEY_PASSWORD_ENV => Some("password".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            super::from_sources_with(&settings, env)?.unwrap(),
+            "password"
+        );
+
+        // Then the passphrase command
+        assert_eq!(
+            super::from_sources_with(&settings, |_| None)?.unwrap(),
+            "other"
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_descriptor() -> anyhow::Result<()> {
+        use crate::constants::KEY_PASSWORD_FD_ENV;
+        use std::io::Write;
+        use std::os::unix::io::AsRawFd;
+
+        let settings = Settings::default();
+
+        // Standard input and outputs are refused, like descriptors which are not numbers
+        for fd in &["0", "1", "2", "-1", "password"] {
+            let env = |name: &str| match name {
+                KEY_PASSWORD_FD_ENV => Some(fd.to_string()),
+                _ => None,
+            };
+            assert_eq!(super::from_sources_with(&settings, env).is_err(), true);
+        }
+
+        let mut file = tempfile::tempfile()?;
+        file.write_all(b"password\nsecond line\n")?;
+        std::io::Seek::rewind(&mut file)?;
+
+        let fd = file.as_raw_fd().to_string();
+        let env = |name: &str| match name {
+            KEY_PASSWORD_FD_ENV => Some(fd.clone()),
+            _ => None,
+        };
+        assert_eq!(
+            super::from_sources_with(&settings, env)?.unwrap(),
+            "password"
+        );
+
+        // File descriptor is still open, it belongs to the caller
+        assert_eq!(file.metadata().is_ok(), true);
+        assert_eq!(
+            unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFD) } != -1,
+            true
+        );
+
+        // It can only be read once, what was read is kept
+        assert_eq!(
+            super::from_sources_with(&settings, env)?.unwrap(),
+            "password"
+        );
+
+        Ok(())
+    }
+}
@@ -1,20 +1,41 @@
-use crate::decrypt::decrypt;
-use crate::encrypt::encrypt;
+use crate::decrypt::{decrypt_with, Decrypted, SignatureStatus};
+use crate::encrypt::{encrypt, encrypt_symmetric};
+use crate::fields;
+use crate::keyring::{load_keyring, load_keys, load_known_certs};
+use crate::passphrase;
+use crate::policy::Policy;
+use crate::prompt;
+use crate::recipients::{find_gpg_id, load_certs, read_verified_gpg_id, resolve_recipients};
+use crate::secret::{read_to_secret, SecretString};
+use crate::settings::{KeyBackend, Settings};
+use crate::sign::signing_keypair;
 use anyhow::{anyhow, Context};
+#[cfg(windows)]
 use clipboard_win::set_clipboard_string;
-use sequoia_openpgp::parse::Parse;
+use sequoia_ipc::gnupg;
+use sequoia_openpgp::crypto;
+use sequoia_openpgp::crypto::KeyPair;
 use sequoia_openpgp::Cert;
-use std::fs::File;
-use std::io::{Error, ErrorKind, Read};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
 
 #[derive(Default)]
 pub struct Password {
+    // Path of password store
+    path_store: Option<PathBuf>,
+
     // Path of file
     path_file: Option<PathBuf>,
 
-    // Password in plain text
-    password: Option<Vec<String>>,
+    // Password in plain text, exactly as in the file
+    password: Option<SecretString>,
+
+    // Passphrase of a symmetric encrypted password
+    passphrase: Option<crypto::Password>,
+
+    // Signature of the decrypted password
+    signature: Option<SignatureStatus>,
 }
 
 impl Password {
@@ -23,17 +44,35 @@ impl Password {
     }
 
     pub fn set_filepath(&mut self, base_path: &Path, password_name: &str) {
-        let mut p = base_path.join(password_name);
+        self.path_store = Some(base_path.to_path_buf());
+
+        let p = base_path.join(password_name);
 
         // If `password_name` is a folder, gtfo
         if p.is_dir() {
             self.path_file = None;
         } else {
-            p.set_extension("gpg");
-            self.path_file = Some(p);
+            // Append extension, names like `example.com` already have one
+            let mut p = p.into_os_string();
+            p.push(".gpg");
+            self.path_file = Some(PathBuf::from(p));
         }
     }
 
+    /// Name of the entry, its path in the password store without `.gpg`
+    fn name(&self) -> String {
+        let path = match &self.path_file {
+            Some(path) => path,
+            None => return String::new(),
+        };
+        let path = match &self.path_store {
+            Some(store) => path.strip_prefix(store).unwrap_or(path),
+            None => path,
+        };
+
+        path.with_extension("").to_string_lossy().to_string()
+    }
+
     pub fn exists(&self) -> bool {
         match &self.path_file {
             Some(path) => path.is_file(),
@@ -41,64 +80,310 @@ impl Password {
         }
     }
 
-    /// Open password in file and decrypt it with `key`
-    pub fn open_decrypt(
-        &mut self,
-        key_path: &Path,
-        password: Option<String>,
-    ) -> anyhow::Result<()> {
+    /// Decrypt password in file with the keys from `settings`,
+    /// `read` gets the plaintext as it is decrypted and the passphrase of the file if it has one
+    ///
+    /// `password` is the password of the keys, a symmetrically encrypted password
+    /// is decrypted with the passphrase of `set_passphrase`, or the one asked
+    ///
+    /// Signatures are checked against the keys and the trusted signers from `settings`,
+    /// once `read` has read all of the plaintext
+    fn decrypt_with<T>(
+        &self,
+        settings: &Settings,
+        password: Option<SecretString>,
+        read: impl FnOnce(&mut dyn Read, Option<&crypto::Password>) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Decrypted<T>> {
         let file_path = self
             .get_filepath()
             .context("Path of password is not set (this should not happen)")?;
+        let key_paths = settings.get_key_paths()?;
+        let certs = load_keys(&key_paths)?;
+
+        // Keys trusted to sign passwords, only known by their fingerprint
+        // so that a key planted in the keyring with the same email is not trusted
+        let mut signers = certs.clone();
+        let trusted_signers = settings.get_trusted_signers()?;
+        if !trusted_signers.is_empty() {
+            let keyring = load_keyring(settings.get_password_store_path()?)?;
+            signers.extend(
+                keyring
+                    .into_iter()
+                    .filter(|cert| trusted_signers.contains(&cert.fingerprint())),
+            );
+        }
 
-        // Attempt to decrypt password, result will be a one-line string
-        let raw_file = decrypt(file_path, key_path, password)?;
+        // Password of key can come from somewhere else than the terminal,
+        // it is only needed if the key is not unlocked in the agent
+        let password_source = || match &password {
+            Some(password) => Ok(Some(password.clone())),
+            None => passphrase::from_sources(settings),
+        };
 
-        // Store lines in vector
-        let vec = string_to_vec(&raw_file).context("Failed to parse password")?;
+        let gpg_agent = gpg_agent_context(settings)?;
+        let decrypted = decrypt_with(
+            file_path,
+            &self.name(),
+            &certs,
+            &password_source,
+            self.passphrase.as_ref(),
+            &signers,
+            gpg_agent.as_ref(),
+            read,
+        )?;
+
+        if settings.get_require_signature()
+            && !matches!(decrypted.signature, SignatureStatus::Good(_))
+        {
+            return Err(anyhow!(
+                "Password {} does not have a good signature: {}",
+                file_path.display(),
+                decrypted.signature
+            ));
+        }
 
-        // Save vector
-        self.password = Some(vec);
+        Ok(decrypted)
+    }
+
+    /// Open password in file and decrypt it with the keys from `settings`
+    ///
+    /// Signatures are checked against the keys and the trusted signers from `settings`
+    pub fn open_decrypt(
+        &mut self,
+        settings: &Settings,
+        password: Option<SecretString>,
+    ) -> anyhow::Result<()> {
+        // Attempt to decrypt password, result will be text
+        let decrypted =
+            self.decrypt_with(settings, password, |plaintext, _| read_to_secret(plaintext))?;
+
+        // Save contents as they are, to write them back unchanged
+        self.password = Some(decrypted.contents);
+
+        // Keep passphrase to encrypt password the same way
+        self.passphrase = decrypted.passphrase;
+
+        self.signature = Some(decrypted.signature);
 
         Ok(())
     }
 
-    /// Encrypt the password using a key contained in the file `key`
-    pub fn encrypt_with_key(&self, key: &Path) -> anyhow::Result<()> {
-        // Create output file
+    /// Decrypt password in file to `output` as it is, without keeping it in memory
+    ///
+    /// Returns the number of bytes written. If the password does not have the signature
+    /// required by `settings`, the error comes once `output` is written
+    pub fn decrypt_to(
+        &mut self,
+        settings: &Settings,
+        password: Option<SecretString>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<u64> {
+        let decrypted = self.decrypt_with(settings, password, |plaintext, _| {
+            Ok(std::io::copy(plaintext, output)?)
+        })?;
+
+        self.signature = Some(decrypted.signature);
+
+        Ok(decrypted.contents)
+    }
+
+    /// Signature of the password, once it is decrypted
+    pub fn get_signature(&self) -> Option<&SignatureStatus> {
+        self.signature.as_ref()
+    }
+
+    /// Get recipients of the password, from the nearest `.gpg-id` file
+    ///
+    /// Keys allowed to sign `.gpg-id` are looked up in `certs`
+    pub fn get_recipients(&self, certs: &[Cert]) -> anyhow::Result<Vec<String>> {
+        let path = self
+            .get_filepath()
+            .context("Path of password is not set (this should not happen)")?;
+        let store_path = self
+            .path_store
+            .as_ref()
+            .context("Path of password store is not set (this should not happen)")?;
+
+        read_verified_gpg_id(&find_gpg_id(store_path, path)?, certs)
+    }
+
+    /// Get policy of the password, from the nearest `.pass-policy` file
+    ///
+    /// Keys allowed to sign `.pass-policy` are looked up in the keys from `settings`
+    pub fn get_policy(&self, settings: &Settings) -> anyhow::Result<Option<Policy>> {
         let path = self
             .get_filepath()
             .context("Path of password is not set (this should not happen)")?;
+        let store_path = self
+            .path_store
+            .as_ref()
+            .context("Path of password store is not set (this should not happen)")?;
+
+        let known_certs = load_known_certs(store_path, &settings.get_key_paths()?)?;
+        Policy::find(store_path, path, &known_certs)
+    }
+
+    /// Check first line of the password against its policy
+    ///
+    /// Breaking the policy is an error, or only a warning with `ignore_policy`
+    pub fn check_policy(&self, settings: &Settings, ignore_policy: bool) -> anyhow::Result<()> {
+        let policy = match self.get_policy(settings)? {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+
+        let broken = policy.check(self.line(1).unwrap_or_default());
+        if broken.is_empty() {
+            return Ok(());
+        }
+
+        let message = format!(
+            "Password does not follow the policy of {}: {}",
+            policy.get_path().display(),
+            broken.join(", ")
+        );
+        if ignore_policy {
+            eprintln!("Warning: {}", message);
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} (use --ignore-policy to keep it anyway)",
+                message
+            ))
+        }
+    }
+
+    /// Protect the password with a passphrase instead of the keys of the recipients
+    pub fn set_passphrase(&mut self, passphrase: &str) {
+        self.passphrase = Some(passphrase.into());
+    }
+
+    /// Encrypt the password for the recipients listed in the nearest `.gpg-id`
+    ///
+    /// Keys of the recipients are looked up in the key file and in the public keyring
+    ///
+    /// If the password has a passphrase, it is encrypted with the passphrase only
+    pub fn encrypt_with_key(&self, settings: &Settings) -> anyhow::Result<()> {
+        let mut contents = self.contents()?.as_bytes();
+
+        self.write_file(|output| {
+            self.encrypt_signed(
+                settings,
+                None,
+                self.passphrase.as_ref(),
+                &mut contents,
+                output,
+            )
+        })
+    }
+
+    /// Encrypt what `input` contains as the password, like `encrypt_with_key`
+    ///
+    /// `input` is read as it is encrypted, it can be a file of any size and content
+    pub fn encrypt_from(&self, settings: &Settings, input: &mut dyn Read) -> anyhow::Result<()> {
+        self.write_file(|output| {
+            self.encrypt_signed(settings, None, self.passphrase.as_ref(), input, output)
+        })
+    }
+
+    /// Encrypt `input` to `output`, signed with the key if `settings` asks for it
+    ///
+    /// `key_password` unlocks the key, it is asked if there is none
+    fn encrypt_signed(
+        &self,
+        settings: &Settings,
+        key_password: Option<&str>,
+        passphrase: Option<&crypto::Password>,
+        input: &mut dyn Read,
+        output: &mut (dyn Write + Send + Sync),
+    ) -> anyhow::Result<()> {
+        let signer = if settings.get_sign_passwords() {
+            let key_password = match key_password {
+                Some(key_password) => Some(key_password.into()),
+                None => passphrase::from_sources(settings)?,
+            };
+            Some(signer(
+                settings.get_pgp_key_path()?,
+                key_password.as_deref(),
+            )?)
+        } else {
+            None
+        };
 
+        if let Some(passphrase) = passphrase {
+            return encrypt_symmetric(input, output, passphrase, signer);
+        }
+
+        // Get recipients of password
+        let store_path = self
+            .path_store
+            .as_ref()
+            .context("Path of password store is not set (this should not happen)")?;
+        let own_certs = load_keys(&settings.get_key_paths()?)?;
+        let keyring = load_keyring(store_path)?;
+        let known_certs = [own_certs.as_slice(), keyring.as_slice()].concat();
+        let recipients = self.get_recipients(&known_certs)?;
+        let certs = resolve_recipients(&recipients, &own_certs, &keyring)?;
+
+        encrypt(input, output, &certs, signer)
+    }
+
+    /// Write the file of the password with `write`, see `write_new_file`
+    fn write_file<T>(
+        &self,
+        write: impl FnOnce(&mut (dyn Write + Send + Sync)) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let path = self
+            .get_filepath()
+            .context("Path of password is not set (this should not happen)")?;
         let parent_path = path.parent().context("Get parent folder")?;
         std::fs::create_dir_all(parent_path).context("Creating folders for password")?;
 
-        let mut output = File::create(path)?;
-
-        // Get pgp key
-        let cert = Cert::from_file(key).context("Failed to load key from file")?;
+        write_new_file(path, write)
+    }
 
-        // Get password contents
-        let contents = self.to_string()?;
+    /// Decrypt the password and encrypt it again for the recipients of its location
+    ///
+    /// `password` unlocks the keys to decrypt, `key_password` unlocks the key to sign.
+    /// The password is encrypted as it is decrypted, it is never kept in memory
+    pub fn reencrypt(
+        &mut self,
+        settings: &Settings,
+        password: Option<SecretString>,
+        key_password: Option<SecretString>,
+    ) -> anyhow::Result<()> {
+        let decrypted = self.write_file(|output| {
+            self.decrypt_with(settings, password, |plaintext, passphrase| {
+                self.encrypt_signed(
+                    settings,
+                    key_password.as_deref(),
+                    passphrase,
+                    plaintext,
+                    output,
+                )
+            })
+        })?;
+
+        // Keep passphrase to encrypt password the same way
+        self.passphrase = decrypted.passphrase;
+
+        self.signature = Some(decrypted.signature);
 
-        encrypt(&contents, &mut output, &cert)
+        Ok(())
     }
 
     #[cfg(test)]
     pub fn from_single_line(s: &str) -> Self {
-        let mut vec = Vec::new();
-        vec.push(s.to_string());
-
         Self {
-            password: Some(vec),
+            password: Some(s.into()),
             ..Default::default()
         }
     }
 
     #[cfg(test)]
-    pub fn from_multi_line(v: &Vec<String>) -> Self {
+    pub fn from_multi_line(v: &[String]) -> Self {
         Self {
-            password: Some(v.clone()),
+            password: Some(v.concat().into()),
             ..Default::default()
         }
     }
@@ -108,82 +393,113 @@ impl Password {
         self.password = None;
     }
 
-    /// Set password from a single line input
+    /// Set password from a single line input, ending it with a newline like pass does
     pub fn single_line_input(&mut self, s: &str) {
-        let mut vec = Vec::new();
-        vec.push(s.into());
-
-        self.password = Some(vec);
+        self.password = Some(single_line(s));
     }
 
-    /// Set password from a multi line input
+    /// Set password from a multi line input, kept as it is
     pub fn multi_line_input(&mut self, s: &str) {
-        self.password = string_to_vec(s);
+        self.password = Some(s.into());
     }
 
-    /// Get password from terminal
-    pub fn terminal_input(&mut self, password_name: &str, multi_line: bool) -> Result<(), Error> {
+    /// Get password from terminal, or from pinentry if there is one
+    pub fn terminal_input(&mut self, password_name: &str, multi_line: bool) -> anyhow::Result<()> {
         // Get input
-        let raw_input = {
+        let output = {
             if multi_line {
-                let mut buffer = String::new();
                 println!(
                     "Enter contents of {} and press Ctrl+Z when finished:",
                     password_name
                 );
-                std::io::stdin().read_to_string(&mut buffer)?;
-
-                buffer
+                read_to_secret(&mut std::io::stdin())?
             } else {
-                let password1 = rpassword::read_password_from_tty(Some(
-                    format!("Enter password for {}: ", password_name).as_str(),
-                ))?;
+                let password1 = prompt::password(
+                    &format!("Enter password for {}", password_name),
+                    "Password",
+                    None,
+                )?;
 
                 // Make sure first entry is not empty to move forward
                 if password1.is_empty() {
-                    return Err(Error::new(ErrorKind::Other, "Empty password"));
+                    return Err(anyhow!("Empty password"));
                 }
-                let password2 = rpassword::read_password_from_tty(Some(
-                    format!("Retype password for {}: ", password_name).as_str(),
-                ))?;
-
-                if password1 != password2 {
-                    return Err(Error::new(ErrorKind::Other, "Passwords do not match"));
+                let password2 = prompt::password(
+                    &format!("Retype password for {}", password_name),
+                    "Password",
+                    None,
+                )?;
+
+                if *password1 != *password2 {
+                    return Err(anyhow!("Passwords do not match"));
                 }
 
-                password1
+                single_line(&password1)
             }
         };
 
-        // Convert one-line password string to vector
-        let output = match string_to_vec(&raw_input) {
-            Some(output) => output,
-            None => return Err(Error::new(ErrorKind::Other, "Empty password")),
-        };
+        if output.is_empty() {
+            return Err(anyhow!("Empty password"));
+        }
 
-        // Save final vector
+        // Save final password
         self.password = Some(output);
 
         Ok(())
     }
 
-    /// Format password as a single block
-    pub fn to_string(&self) -> anyhow::Result<String> {
-        let mut string = String::new();
+    /// Replace first line of password with `line`, the other lines are kept as they are
+    pub fn set_first_line(&mut self, line: &str) -> anyhow::Result<()> {
+        let contents = self.contents()?;
+        let first_line = contents.lines().next().unwrap_or_default();
 
-        let password = self
-            .password
-            .as_ref()
-            .context("There is no password, this should never happen")?;
+        // Line ending of the first line and the next lines, the line is ended if it is alone
+        let rest = match &contents[first_line.len()..] {
+            "" => "\n",
+            rest => rest,
+        };
+
+        let mut replaced = SecretString::with_capacity(line.len() + rest.len());
+        replaced.push_str(line);
+        replaced.push_str(rest);
+        self.password = Some(replaced);
 
-        for line in password {
-            string.push_str(&line);
+        Ok(())
+    }
+
+    /// Add `lines` at the end of password, on their own lines and ending with a newline
+    ///
+    /// Lines end like the lines of the password, with `\r\n` if it has some
+    pub fn append(&mut self, lines: &str) -> anyhow::Result<()> {
+        let contents = self.contents()?;
+        let ending = if contents.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+
+        let mut appended = SecretString::with_capacity(contents.len() + 2 * lines.len() + 2);
+        appended.push_str(contents);
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            appended.push_str(ending);
+        }
+        for line in lines.lines() {
+            appended.push_str(line);
+            appended.push_str(ending);
         }
+        self.password = Some(appended);
 
-        Ok(string)
+        Ok(())
+    }
+
+    /// Get password as a single block
+    pub fn contents(&self) -> anyhow::Result<&str> {
+        self.password
+            .as_deref()
+            .context("There is no password, this should never happen")
     }
 
-    /// Get specific line of password
+    /// Get specific line of password, without its line ending
     pub fn line(&self, line: usize) -> Option<&str> {
         let line = {
             // Try to prevent from accessing line `-1`
@@ -195,7 +511,47 @@ impl Password {
             }
         };
 
-        Some(self.password.as_ref()?.get(line)?.as_str())
+        self.password.as_ref()?.lines().nth(line)
+    }
+
+    /// Get value of field `name` of password (`name: value` lines after the first one)
+    ///
+    /// Names are not case sensitive
+    pub fn field(&self, name: &str) -> Option<SecretString> {
+        fields::parse(self.password.as_ref()?)
+            .into_iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Get names of the fields of password
+    pub fn field_names(&self) -> Vec<&str> {
+        match &self.password {
+            Some(password) => fields::parse(password)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Set field `name` of password to `value`, the password and other lines are kept
+    pub fn set_field(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        let contents = fields::set(self.contents()?, name, value)?;
+        self.password = Some(contents);
+
+        Ok(())
+    }
+
+    /// Remove field `name` of password, returns `false` if there is no such field
+    pub fn unset_field(&mut self, name: &str) -> anyhow::Result<bool> {
+        match fields::unset(self.contents()?, name) {
+            Some(contents) => {
+                self.password = Some(contents);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
     /// Display padding based off password's length
@@ -215,17 +571,7 @@ impl Password {
             .line(line)
             .context(format!("Failed to get line {}", line))?;
 
-        let first_line_len = {
-            let mut len = first_line.len();
-
-            // dont count the final \n otherwise it will look bad
-            if first_line.ends_with('\n') {
-                len -= 1;
-            }
-            len
-        };
-
-        for _ in 0..first_line_len {
+        for _ in 0..first_line.len() {
             print!("=");
         }
         println!();
@@ -234,45 +580,96 @@ impl Password {
     }
 }
 
-/// Convert a one-line password to a vector
-fn string_to_vec(input: &str) -> Option<Vec<String>> {
-    let mut vec = Vec::new();
-
-    // Iterator over all lines from input
-    let mut iter = input.lines();
-
-    // Get first line
-    let mut first = match iter.next() {
-        Some(first) => first.to_string(),
-        None => return None,
+/// Connect to gpg-agent if `settings` use it for the secret key
+fn gpg_agent_context(settings: &Settings) -> anyhow::Result<Option<gnupg::Context>> {
+    let context = match settings.get_key_backend() {
+        KeyBackend::File => return Ok(None),
+        KeyBackend::GpgAgent => match settings.get_gnupg_home() {
+            Some(gnupg_home) => gnupg::Context::with_homedir(gnupg_home),
+            None => gnupg::Context::new(),
+        },
     };
 
-    // Try to get second line (if it's a multiline input)
-    match iter.next() {
-        Some(second) => {
-            // If input is multiline, add a unix newline for first line
-            first.push('\n');
-            vec.push(first);
-
-            // Do the same for the second line (otherwise it will be lost)
-            let mut second = second.to_string();
-            second.push('\n');
-            vec.push(second);
-        }
-        // If there is no second line return first line without newline
-        None => vec.push(first),
+    Ok(Some(context.context("Failed to find gpg-agent")?))
+}
+
+/// Get the signing key of the key file at `key`
+fn signer(key: &Path, key_password: Option<&str>) -> anyhow::Result<KeyPair> {
+    let cert = load_certs(key)?
+        .into_iter()
+        .next()
+        .context("Failed to load key from file")?;
+
+    if !cert.is_tsk() {
+        return Err(anyhow!(
+            "Key {} is a public key only, its secret key is needed to sign passwords",
+            cert.fingerprint()
+        ));
     }
 
-    // Iterate over the rest of the multiline input
-    for line in iter {
-        // Add unix newline character at the end of each line
-        let mut line = line.to_string();
-        line.push('\n');
+    signing_keypair(&cert, key_password)
+}
 
-        vec.push(line);
+/// Get passphrase for a symmetric encrypted password from terminal, or from pinentry if there is one
+pub fn passphrase_input(password_name: &str) -> anyhow::Result<SecretString> {
+    let passphrase1 = prompt::password(
+        &format!("Enter passphrase to protect {}", password_name),
+        "Passphrase",
+        None,
+    )?;
+
+    // Make sure first entry is not empty to move forward
+    if passphrase1.is_empty() {
+        return Err(anyhow!("Empty passphrase"));
+    }
+    let passphrase2 = prompt::password(
+        &format!("Retype passphrase to protect {}", password_name),
+        "Passphrase",
+        None,
+    )?;
+
+    if *passphrase1 != *passphrase2 {
+        return Err(anyhow!("Passphrases do not match"));
     }
 
-    Some(vec)
+    Ok(passphrase1)
+}
+
+/// Write the file at `path` with `write`
+///
+/// A temporary file, only readable by the user, is written next to it first:
+/// the file is replaced only if `write` succeeds
+pub fn write_new_file<T>(
+    path: &Path,
+    write: impl FnOnce(&mut (dyn Write + Send + Sync)) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let folder = match path.parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+
+    // Temporary file is removed when it is dropped, if `write` fails
+    let mut file = NamedTempFile::new_in(folder)
+        .with_context(|| format!("Failed to create a file in {}", folder.display()))?;
+    let value = write(file.as_file_mut())?;
+    file.persist(path)?;
+
+    Ok(value)
+}
+
+/// Password made of the line `s`, ending with a newline
+fn single_line(s: &str) -> SecretString {
+    let mut password = SecretString::with_capacity(s.len() + 1);
+    password.push_str(s);
+    password.push('\n');
+
+    password
+}
+
+/// Clipboard is only supported on Windows
+#[cfg(not(windows))]
+fn set_clipboard_string(_s: &str) -> anyhow::Result<()> {
+    Err(anyhow!("Clipboard is not supported on this platform"))
 }
 
 /// Set a password to the clipboard
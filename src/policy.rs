@@ -0,0 +1,272 @@
+use crate::constants::POLICY_FILE;
+use crate::generator::Characters;
+use crate::recipients::{find_nearest, signing_keys, verify_file};
+use anyhow::Context;
+use sequoia_openpgp::{Cert, Fingerprint};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Rules for the passwords of a folder, from the nearest `.pass-policy` file
+///
+/// The file is TOML, all rules are optional:
+/// ```toml
+/// min-length = 32
+/// exclude = " "
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Policy {
+    #[serde(skip)]
+    path: PathBuf,
+
+    // Length of generated passwords
+    length: Option<usize>,
+
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+
+    // Classes of characters which can be in passwords
+    lowercase: Option<bool>,
+    uppercase: Option<bool>,
+    digits: Option<bool>,
+    symbols: Option<bool>,
+
+    // Only characters which can be in passwords, instead of the classes
+    allowed: Option<String>,
+
+    // Characters which can not be in passwords
+    exclude: Option<String>,
+
+    // Minimum number of characters of each class which can be in passwords
+    min_per_class: Option<usize>,
+}
+
+/// Tells if a character is in a class
+type IsClass = fn(&char) -> bool;
+
+/// Names of the classes of characters, and how to recognize them
+const CLASSES: [(&str, IsClass); 4] = [
+    ("lowercase letters", char::is_ascii_lowercase),
+    ("uppercase letters", char::is_ascii_uppercase),
+    ("digits", char::is_ascii_digit),
+    ("symbols", |c| !c.is_ascii_alphanumeric()),
+];
+
+impl Policy {
+    fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let policy_raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to open policy file {}", path.display()))?;
+
+        let mut policy: Policy = toml::from_str(&policy_raw)
+            .with_context(|| format!("Failed to load policy from file {}", path.display()))?;
+        policy.path = path.to_path_buf();
+
+        Ok(policy)
+    }
+
+    /// Find the policy of the entry at `path`, in the nearest `.pass-policy` file
+    ///
+    /// Like `.gpg-id`, it has to be signed if signing keys are configured,
+    /// keys of the signers are looked up in `certs`
+    pub fn find(store_path: &Path, path: &Path, certs: &[Cert]) -> anyhow::Result<Option<Self>> {
+        Self::find_signed(store_path, path, &signing_keys()?, certs)
+    }
+
+    fn find_signed(
+        store_path: &Path,
+        path: &Path,
+        signing_keys: &[Fingerprint],
+        certs: &[Cert],
+    ) -> anyhow::Result<Option<Self>> {
+        let policy_path = match find_nearest(store_path, path, POLICY_FILE)? {
+            Some(policy_path) => policy_path,
+            None => return Ok(None),
+        };
+
+        // Otherwise anyone who can write to the store can weaken the passwords
+        if !signing_keys.is_empty() {
+            verify_file(&policy_path, signing_keys, certs)?;
+        }
+
+        Ok(Some(Self::from_path(&policy_path)?))
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Generate passwords following the policy, unless told otherwise
+    pub fn characters(&self) -> Characters {
+        let default = Characters::default();
+
+        let length = self.length.unwrap_or_else(|| {
+            let length = default.length.max(self.min_length.unwrap_or(0));
+            self.max_length
+                .map_or(length, |max_length| length.min(max_length))
+        });
+
+        Characters {
+            length,
+            lowercase: self.lowercase.unwrap_or(default.lowercase),
+            uppercase: self.uppercase.unwrap_or(default.uppercase),
+            digits: self.digits.unwrap_or(default.digits),
+            symbols: self.symbols.unwrap_or(default.symbols),
+            allowed: self.allowed.clone(),
+            excluded: self.exclude.clone().unwrap_or_default(),
+            min_per_class: self.min_per_class.unwrap_or(default.min_per_class),
+        }
+    }
+
+    /// Check `password` against the policy, returns the rules it breaks
+    pub fn check(&self, password: &str) -> Vec<String> {
+        let mut broken = Vec::new();
+
+        let length = password.chars().count();
+        if let Some(min_length) = self.min_length.filter(|min_length| length < *min_length) {
+            broken.push(format!("it is shorter than {} characters", min_length));
+        }
+        if let Some(max_length) = self.max_length.filter(|max_length| length > *max_length) {
+            broken.push(format!("it is longer than {} characters", max_length));
+        }
+
+        if let Some(allowed) = &self.allowed {
+            if password.chars().any(|c| !allowed.contains(c)) {
+                broken.push("it has characters which are not allowed".to_string());
+            }
+        }
+        if let Some(exclude) = &self.exclude {
+            if password.chars().any(|c| exclude.contains(c)) {
+                broken.push("it has excluded characters".to_string());
+            }
+        }
+
+        let enabled = [self.lowercase, self.uppercase, self.digits, self.symbols];
+        for ((name, is_class), enabled) in CLASSES.iter().zip(&enabled) {
+            let count = password.chars().filter(is_class).count();
+
+            // With allowed characters, classes are the ones they have
+            let used = match &self.allowed {
+                Some(allowed) => allowed.chars().any(|c| is_class(&c)),
+                None => enabled.unwrap_or(true),
+            };
+
+            if !used && count > 0 {
+                broken.push(format!("it has {}", name));
+            }
+            match self.min_per_class {
+                Some(min_per_class) if used && count < min_per_class => {
+                    broken.push(format!("it has less than {} {}", min_per_class, name))
+                }
+                _ => (),
+            }
+        }
+
+        broken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Policy;
+    use crate::recipients::{load_certs, sign_file};
+    use sequoia_openpgp::Fingerprint;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn find_policy() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let store_path = tmp_dir.path();
+        std::fs::create_dir_all(store_path.join("bank/personal"))?;
+        File::create(store_path.join("bank/.pass-policy"))?
+            .write_all(b"min-length = 32\nexclude = \" \"\n")?;
+
+        let policy = Policy::find(store_path, &store_path.join("bank/personal/main.gpg"), &[])?;
+        assert_eq!(policy.is_some(), true);
+        assert_eq!(
+            Policy::find(store_path, &store_path.join("email.gpg"), &[])?.is_none(),
+            true
+        );
+
+        let policy = policy.unwrap();
+        assert_eq!(policy.get_path(), store_path.join("bank/.pass-policy"));
+        assert_eq!(policy.characters().length, 32);
+        assert_eq!(policy.check("short password").len(), 2);
+        assert_eq!(policy.check(&"a".repeat(32)).is_empty(), true);
+
+        // Unknown rules are mistakes
+        File::create(store_path.join(".pass-policy"))?.write_all(b"min-lenght = 8\n")?;
+        assert_eq!(
+            Policy::find(store_path, &store_path.join("email.gpg"), &[]).is_err(),
+            true
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn signed_policy() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let store_path = tmp_dir.path();
+        let policy_path = store_path.join(".pass-policy");
+        File::create(&policy_path)?.write_all(b"min-length = 32\n")?;
+
+        let secret_key_path = format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"));
+        let certs = load_certs(secret_key_path.as_ref())?;
+        let signing_keys = vec![Fingerprint::from_hex(
+            "AF6DF58D346543BD06CFFE9922A2542A924C5648",
+        )?];
+        let find = || {
+            Policy::find_signed(
+                store_path,
+                &store_path.join("email.gpg"),
+                &signing_keys,
+                &certs,
+            )
+        };
+
+        // Not signed yet
+        assert_eq!(find().is_err(), true);
+
+        sign_file(
+            &policy_path,
+            secret_key_path.as_ref(),
+            Some("password"),
+            &signing_keys,
+        )?;
+        assert_eq!(find()?.unwrap().characters().length, 32);
+
+        // Weakened policy
+        File::create(&policy_path)?.write_all(b"min-length = 4\n")?;
+        assert_eq!(find().is_err(), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_pin() -> anyhow::Result<()> {
+        let policy: Policy = toml::from_str(
+            "length = 6\nmin-length = 6\nmax-length = 6\nallowed = \"0123456789\"\n",
+        )?;
+
+        let generated = policy.characters().generate()?;
+        assert_eq!(policy.check(&generated).is_empty(), true);
+
+        assert_eq!(policy.check("123456").is_empty(), true);
+        assert_eq!(
+            policy.check("12345a"),
+            [
+                "it has characters which are not allowed",
+                "it has lowercase letters"
+            ]
+        );
+        assert_eq!(policy.check("1234").len(), 1);
+
+        let policy: Policy = toml::from_str("symbols = false\nmin-per-class = 1\n")?;
+        assert_eq!(policy.check("Password1").is_empty(), true);
+        assert_eq!(policy.check("password!").len(), 3);
+
+        Ok(())
+    }
+}
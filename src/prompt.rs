@@ -0,0 +1,309 @@
+use crate::secret::SecretString;
+use anyhow::{anyhow, Context};
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+thread_local! {
+    // Pinentry program to use instead of the terminal, from the settings
+    static PINENTRY: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Ask passwords and confirmations with `pinentry` instead of the terminal, if there is one
+pub fn use_pinentry(pinentry: Option<&Path>) {
+    PINENTRY.with(|cell| *cell.borrow_mut() = pinentry.map(Path::to_path_buf));
+}
+
+fn pinentry() -> Option<PathBuf> {
+    PINENTRY.with(|cell| cell.borrow().clone())
+}
+
+/// Escape `s` to be sent as a parameter of an Assuan command
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '\n' => escaped.push_str("%0A"),
+            '\r' => escaped.push_str("%0D"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Decode `%XX` sequences of data received from Assuan
+fn unescape(s: &str) -> anyhow::Result<SecretString> {
+    // Decoded data is never longer, `bytes` does not move
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [
+                iter.next().context("Truncated escape sequence")?,
+                iter.next().context("Truncated escape sequence")?,
+            ];
+            let hex = std::str::from_utf8(&hex)?;
+            bytes.push(u8::from_str_radix(hex, 16)?);
+        } else {
+            bytes.push(b);
+        }
+    }
+
+    SecretString::from_utf8(bytes)
+}
+
+/// Conversation with a pinentry, using the Assuan protocol
+struct Conversation<R: BufRead, W: Write> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> Conversation<R, W> {
+    /// Start conversation, pinentry greets us first
+    fn new(reader: R, writer: W) -> anyhow::Result<Self> {
+        let mut conversation = Self { reader, writer };
+        conversation
+            .response()?
+            .map_err(|e| anyhow!("Pinentry failed to start: {}", e))?;
+
+        Ok(conversation)
+    }
+
+    /// Read response of pinentry: its data if it is `OK`, the error if it is `ERR`
+    fn response(&mut self) -> anyhow::Result<Result<SecretString, String>> {
+        let mut data = SecretString::default();
+
+        loop {
+            // Lines of data are at most 1000 bytes long
+            let mut line = String::with_capacity(1024);
+            let read = self.reader.read_line(&mut line);
+            let line = SecretString::from(line);
+            if read? == 0 {
+                return Err(anyhow!("Pinentry stopped unexpectedly"));
+            }
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line == "OK" || line.starts_with("OK ") {
+                return Ok(Ok(data));
+            } else if let Some(error) = line.strip_prefix("ERR ") {
+                return Ok(Err(error.to_string()));
+            } else if let Some(d) = line.strip_prefix("D ") {
+                data.push_str(&unescape(d)?);
+            }
+            // Status lines and comments are ignored
+        }
+    }
+
+    fn command(&mut self, command: &str) -> anyhow::Result<Result<SecretString, String>> {
+        self.writer.write_all(format!("{}\n", command).as_bytes())?;
+        self.writer.flush()?;
+
+        self.response()
+    }
+
+    /// Send a command which has to succeed
+    fn set(&mut self, command: &str, value: &str) -> anyhow::Result<()> {
+        self.command(&format!("{} {}", command, escape(value)))?
+            .map_err(|e| anyhow!("Pinentry refused {}: {}", command, e))?;
+
+        Ok(())
+    }
+
+    fn get_pin(
+        &mut self,
+        description: &str,
+        prompt: &str,
+        error: Option<&str>,
+    ) -> anyhow::Result<SecretString> {
+        self.set("SETDESC", description)?;
+        self.set("SETPROMPT", prompt)?;
+        if let Some(error) = error {
+            self.set("SETERROR", error)?;
+        }
+
+        self.command("GETPIN")?
+            .map_err(|e| anyhow!("No password entered: {}", e))
+    }
+
+    fn confirm(&mut self, description: &str) -> anyhow::Result<bool> {
+        self.set("SETDESC", description)?;
+
+        // Any error means the user did not confirm
+        Ok(self.command("CONFIRM")?.is_ok())
+    }
+}
+
+/// Spawn `program` and start a conversation with it
+fn start(program: &Path) -> anyhow::Result<(Child, Conversation<impl BufRead, impl Write>)> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start pinentry {}", program.display()))?;
+
+    let reader = BufReader::new(child.stdout.take().context("Failed to talk to pinentry")?);
+    let writer = child.stdin.take().context("Failed to talk to pinentry")?;
+
+    Ok((child, Conversation::new(reader, writer)?))
+}
+
+/// Stop conversation and wait for pinentry to exit
+fn stop(mut child: Child, mut conversation: Conversation<impl BufRead, impl Write>) {
+    let _ = conversation.command("BYE");
+    let _ = child.wait();
+}
+
+/// Ask a password, `description` tells what it is for
+///
+/// `prompt` is only shown by pinentry, next to the input field.
+/// If the previous attempt failed, `error` is shown
+pub fn password(
+    description: &str,
+    prompt: &str,
+    error: Option<&str>,
+) -> anyhow::Result<SecretString> {
+    match pinentry() {
+        Some(program) => {
+            let (child, mut conversation) = start(&program)?;
+            let pin = conversation.get_pin(description, prompt, error);
+            stop(child, conversation);
+
+            pin
+        }
+        None => {
+            // Pinentry has its own button to cancel
+            if let Some(error) = error {
+                eprintln!("{} (press Ctrl+C to cancel)", error);
+            }
+            Ok(rpassword::read_password_from_tty(Some(&format!("{}: ", description)))?.into())
+        }
+    }
+}
+
+/// Ask the user to confirm `description`
+pub fn confirm(description: &str) -> anyhow::Result<bool> {
+    match pinentry() {
+        Some(program) => {
+            let (child, mut conversation) = start(&program)?;
+            let confirmed = conversation.confirm(description);
+            stop(child, conversation);
+
+            confirmed
+        }
+        None => Ok(dialoguer::Confirm::new()
+            .with_prompt(description)
+            .default(false)
+            .show_default(true)
+            .interact()?),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Conversation;
+    use std::io::Cursor;
+    #[cfg(not(windows))]
+    use std::path::{Path, PathBuf};
+
+    /// Write a fake pinentry in `folder`, answering `OK` to everything and `pin` to GETPIN
+    ///
+    /// Returns its path
+    #[cfg(not(windows))]
+    pub fn fake_pinentry(folder: &Path, pin: &str) -> anyhow::Result<PathBuf> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let pinentry = folder.join("pinentry");
+        std::fs::write(
+            &pinentry,
+            format!(
+                "#!/bin/sh\n\
+                 echo OK Pleased to meet you\n\
+                 while read command; do\n\
+                 case \"$command\" in\n\
+                 GETPIN) echo D {}; echo OK;;\n\
+                 BYE) echo OK; exit 0;;\n\
+                 *) echo OK;;\n\
+                 esac\n\
+                 done\n",
+                super::escape(pin)
+            ),
+        )?;
+        std::fs::set_permissions(&pinentry, std::fs::Permissions::from_mode(0o755))?;
+
+        Ok(pinentry)
+    }
+
+    #[test]
+    fn escape_unescape() -> anyhow::Result<()> {
+        assert_eq!(super::escape("100%\nsure"), "100%25%0Asure");
+        assert_eq!(super::unescape("100%25%0Asure")?, "100%\nsure");
+        assert_eq!(super::unescape("%2").is_err(), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_pin() -> anyhow::Result<()> {
+        // Scripted answers of a pinentry
+        let answers = "OK Pleased to meet you\n\
+                       OK\n\
+                       OK\n\
+                       # comment\n\
+                       D pass%25word\n\
+                       OK\n";
+        let mut commands = Vec::new();
+
+        let mut conversation = Conversation::new(Cursor::new(answers), &mut commands)?;
+        let pin = conversation.get_pin("Decrypt entry\nwith key", "Password", None)?;
+        assert_eq!(pin, "pass%word");
+
+        assert_eq!(
+            String::from_utf8(commands)?,
+            "SETDESC Decrypt entry%0Awith key\nSETPROMPT Password\nGETPIN\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cancelled() -> anyhow::Result<()> {
+        let answers = "OK Pleased to meet you\n\
+                       OK\n\
+                       OK\n\
+                       ERR 83886179 Operation cancelled <Pinentry>\n\
+                       OK\n\
+                       ERR 83886179 Operation cancelled <Pinentry>\n";
+        let mut commands = Vec::new();
+
+        let mut conversation = Conversation::new(Cursor::new(answers), &mut commands)?;
+        assert_eq!(
+            conversation
+                .get_pin("Decrypt entry", "Password", None)
+                .is_err(),
+            true
+        );
+        assert_eq!(conversation.confirm("Overwrite entry?")?, false);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn pinentry_program() -> anyhow::Result<()> {
+        let tmp_dir = tempfile::tempdir()?;
+        let pinentry = fake_pinentry(tmp_dir.path(), "password")?;
+
+        super::use_pinentry(Some(&pinentry));
+        assert_eq!(
+            super::password("Decrypt entry", "Password", None)?,
+            "password"
+        );
+        assert_eq!(super::confirm("Overwrite entry?")?, true);
+        super::use_pinentry(None);
+
+        Ok(())
+    }
+}
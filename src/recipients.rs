@@ -0,0 +1,551 @@
+use crate::constants::{GPG_ID_FILE, SIGNING_KEY_ENV};
+use crate::sign::signing_keypair;
+use anyhow::{anyhow, Context};
+use sequoia_openpgp::cert::CertParser;
+use sequoia_openpgp::parse::stream::{
+    DetachedVerifierBuilder, MessageLayer, MessageStructure, VerificationHelper,
+};
+use sequoia_openpgp::parse::Parse;
+use sequoia_openpgp::policy::StandardPolicy;
+use sequoia_openpgp::serialize::stream::{Message, Signer};
+use sequoia_openpgp::{Cert, Fingerprint, KeyHandle};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Read recipients listed in a `.gpg-id` file
+///
+/// There is one recipient per line, empty lines and comments (starting with `#`) are ignored
+pub fn read_gpg_id(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let recipients = contents
+        .lines()
+        .map(|line| match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        })
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    if recipients.is_empty() {
+        return Err(anyhow!("There are no recipients in {}", path.display()));
+    }
+
+    Ok(recipients)
+}
+
+/// Keys allowed to sign `.gpg-id` and `.pass-policy` files
+///
+/// Like pass, they are listed in the environment variable `PASSWORD_STORE_SIGNING_KEY`,
+/// separated by spaces. When there are none, signatures are not checked.
+///
+/// Only full fingerprints of primary keys are accepted: key ids and emails can be
+/// shared by keys of an attacker.
+pub fn signing_keys() -> anyhow::Result<Vec<Fingerprint>> {
+    match std::env::var(SIGNING_KEY_ENV) {
+        Ok(keys) => keys
+            .split_whitespace()
+            .map(|key| {
+                parse_fingerprint(key)
+                    .with_context(|| format!("Invalid key in {}", SIGNING_KEY_ENV))
+            })
+            .collect(),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Parse a full fingerprint, with or without `0x`
+pub fn parse_fingerprint(key: &str) -> anyhow::Result<Fingerprint> {
+    let hex = key.trim_start_matches("0x").trim_start_matches("0X");
+
+    match Fingerprint::from_hex(hex) {
+        Ok(Fingerprint::Invalid(_)) | Err(_) => {
+            Err(anyhow!("`{}` is not the full fingerprint of a key", key))
+        }
+        Ok(fingerprint) => Ok(fingerprint),
+    }
+}
+
+/// Path of the detached signature of a file, like `.gpg-id.sig`
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(".sig");
+
+    PathBuf::from(signature_path)
+}
+
+/// Sign a file like `.gpg-id` with the first key of file `key` listed in `signing_keys`
+///
+/// The detached signature is written next to it, like `.gpg-id.sig`, its path is returned
+pub fn sign_file(
+    path: &Path,
+    key: &Path,
+    password: Option<&str>,
+    signing_keys: &[Fingerprint],
+) -> anyhow::Result<PathBuf> {
+    let cert = load_certs(key)?
+        .into_iter()
+        .find(|cert| signing_keys.contains(&cert.fingerprint()))
+        .with_context(|| {
+            format!(
+                "The key is not one of the keys allowed to sign {}",
+                path.display()
+            )
+        })?;
+
+    let keypair = signing_keypair(&cert, password)?;
+
+    let mut contents = Vec::new();
+    File::open(path)?.read_to_end(&mut contents)?;
+
+    let signature_path = signature_path(path);
+    let output = File::create(&signature_path)
+        .with_context(|| format!("Failed to create {}", signature_path.display()))?;
+
+    let message = Message::new(output);
+    let mut message = Signer::new(message, keypair).detached().build()?;
+    message.write_all(&contents)?;
+    message.finalize()?;
+
+    Ok(signature_path)
+}
+
+/// Accept a detached signature made by one of the allowed keys
+struct SignatureVerifier<'a> {
+    certs: &'a [Cert],
+    signing_keys: &'a [Fingerprint],
+}
+
+impl<'a> VerificationHelper for SignatureVerifier<'a> {
+    fn get_certs(&mut self, _ids: &[KeyHandle]) -> sequoia_openpgp::Result<Vec<Cert>> {
+        Ok(self.certs.to_vec())
+    }
+
+    fn check(&mut self, structure: MessageStructure) -> sequoia_openpgp::Result<()> {
+        for layer in structure.into_iter() {
+            if let MessageLayer::SignatureGroup { results } = layer {
+                for result in results.into_iter().flatten() {
+                    let fingerprint = result.ka.key().fingerprint();
+
+                    // Certificate of the key that made the signature
+                    let cert = self.certs.iter().find(|cert| {
+                        cert.keys()
+                            .any(|key| key.key().fingerprint() == fingerprint)
+                    });
+
+                    if let Some(cert) = cert {
+                        if self.signing_keys.contains(&cert.fingerprint()) {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+
+        Err(anyhow!("No valid signature from an allowed key"))
+    }
+}
+
+/// Make sure a file like `.gpg-id` has a valid signature made by one of `signing_keys`
+///
+/// Keys of the signers are looked up in `certs`
+pub fn verify_file(
+    path: &Path,
+    signing_keys: &[Fingerprint],
+    certs: &[Cert],
+) -> anyhow::Result<()> {
+    let signature = signature_path(path);
+    if !signature.is_file() {
+        return Err(anyhow!(
+            "{} is not signed, refusing to use it",
+            path.display()
+        ));
+    }
+
+    let policy = &StandardPolicy::new();
+    let helper = SignatureVerifier {
+        certs,
+        signing_keys,
+    };
+
+    DetachedVerifierBuilder::from_file(&signature)?
+        .with_policy(policy, None, helper)
+        .and_then(|mut verifier| verifier.verify_file(path))
+        .with_context(|| {
+            format!(
+                "Signature of {} is invalid, refusing to use it",
+                path.display()
+            )
+        })
+}
+
+/// Read recipients listed in a `.gpg-id` file, after checking its signature
+/// if signing keys are configured
+pub fn read_verified_gpg_id(gpg_id_path: &Path, certs: &[Cert]) -> anyhow::Result<Vec<String>> {
+    let signing_keys = signing_keys()?;
+    if !signing_keys.is_empty() {
+        verify_file(gpg_id_path, &signing_keys, certs)?;
+    }
+
+    read_gpg_id(gpg_id_path)
+}
+
+/// Write recipients to a `.gpg-id` file, one per line
+pub fn write_gpg_id(path: &Path, recipients: &[String]) -> anyhow::Result<()> {
+    let mut contents = String::new();
+    for recipient in recipients {
+        contents.push_str(recipient);
+        contents.push('\n');
+    }
+
+    let mut file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+/// Find the `.gpg-id` file that applies to the entry at `path`
+///
+/// Walks up from the folder of the entry until the root of the password store
+pub fn find_gpg_id(store_path: &Path, path: &Path) -> anyhow::Result<PathBuf> {
+    find_nearest(store_path, path, GPG_ID_FILE)?.with_context(|| {
+        format!(
+            "Could not find a {} file for {}",
+            GPG_ID_FILE,
+            path.display()
+        )
+    })
+}
+
+/// Find the file named `name` nearest to the entry at `path`, like `.gpg-id`
+///
+/// Walks up from the folder of the entry until the root of the password store
+pub fn find_nearest(store_path: &Path, path: &Path, name: &str) -> anyhow::Result<Option<PathBuf>> {
+    if !path.starts_with(store_path) {
+        return Err(anyhow!(
+            "{} is not inside the password store",
+            path.display()
+        ));
+    }
+
+    let mut folder = path.parent();
+    while let Some(current) = folder {
+        let file = current.join(name);
+        if file.is_file() {
+            return Ok(Some(file));
+        }
+
+        // Stop when we are at the root of password store
+        if current == store_path {
+            break;
+        }
+        folder = current.parent();
+    }
+
+    Ok(None)
+}
+
+/// Load all certificates contained in a file (a single key or a keyring)
+pub fn load_certs(path: &Path) -> anyhow::Result<Vec<Cert>> {
+    CertParser::from_file(path)
+        .with_context(|| format!("Failed to load keys from file {}", path.display()))?
+        .collect::<sequoia_openpgp::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to parse keys from file {}", path.display()))
+}
+
+/// Key id or fingerprint in `recipient`, as upper case hex without `0x` nor spaces
+///
+/// Returns `None` if `recipient` is not made of hex digits, like an email address
+fn hex_key_id(recipient: &str) -> Option<String> {
+    let hex = recipient
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .replace(' ', "")
+        .to_uppercase();
+
+    if hex.len() >= 8 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(hex)
+    } else {
+        None
+    }
+}
+
+/// Check if `recipient` designates `cert`, like gpg does with `--recipient`
+///
+/// `recipient` can be:
+/// - the fingerprint of the primary key or of a subkey (with or without `0x`)
+/// - the long key id (16 hex digits) of the primary key or of a subkey
+/// - an email address, with or without `<>`
+///
+/// Short key ids (8 hex digits) never match, they are too easy to collide with.
+pub fn cert_matches(cert: &Cert, recipient: &str) -> bool {
+    // Fingerprint or key id
+    if let Some(hex) = hex_key_id(recipient) {
+        return hex.len() >= 16
+            && cert
+                .keys()
+                .any(|key| key.key().fingerprint().to_hex().ends_with(&hex));
+    }
+
+    // Email address
+    let email = recipient
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_lowercase();
+
+    cert.userids().any(|uid| match uid.userid().email2() {
+        Ok(Some(address)) => address.to_lowercase() == email,
+        _ => false,
+    })
+}
+
+/// Find the certificate designated by `recipient` in `certs`
+///
+/// Returns `None` if there is none, fails if several certificates match
+/// or if `recipient` is a short key id.
+pub fn find_cert<'a>(certs: &'a [Cert], recipient: &str) -> anyhow::Result<Option<&'a Cert>> {
+    if matches!(hex_key_id(recipient), Some(hex) if hex.len() < 16) {
+        return Err(anyhow!(
+            "`{}` is a short key id, please use a long key id or a fingerprint",
+            recipient
+        ));
+    }
+
+    let mut matching: Vec<&Cert> = Vec::new();
+    for cert in certs.iter().filter(|cert| cert_matches(cert, recipient)) {
+        // Same key loaded from several files
+        if !matching
+            .iter()
+            .any(|c| c.fingerprint() == cert.fingerprint())
+        {
+            matching.push(cert);
+        }
+    }
+
+    match matching.len() {
+        0 => Ok(None),
+        1 => Ok(Some(matching[0])),
+        _ => Err(anyhow!(
+            "Several keys match `{}`, please use a fingerprint",
+            recipient
+        )),
+    }
+}
+
+/// Find the certificate of each recipient, in `own_certs` (the keys of the user)
+/// or else in `keyring`
+///
+/// Fails if one of the recipients can not be found, or if several keys match it
+pub fn resolve_recipients(
+    recipients: &[String],
+    own_certs: &[Cert],
+    keyring: &[Cert],
+) -> anyhow::Result<Vec<Cert>> {
+    let mut resolved: Vec<Cert> = Vec::new();
+
+    for recipient in recipients {
+        let cert = match find_cert(own_certs, recipient)? {
+            Some(cert) => cert,
+            None => find_cert(keyring, recipient)?
+                .with_context(|| format!("Could not find a key for recipient `{}`", recipient))?,
+        };
+
+        // Same key listed multiple times
+        if resolved
+            .iter()
+            .any(|c| c.fingerprint() == cert.fingerprint())
+        {
+            continue;
+        }
+
+        resolved.push(cert.clone());
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use sequoia_openpgp::cert::CertBuilder;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn secret_key_path() -> String {
+        format!("{}/tests/secret-key.asc", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    #[test]
+    fn read_gpg_id() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let gpg_id_path = tmp_dir.path().join(".gpg-id");
+
+        let mut file = File::create(&gpg_id_path)?;
+        file.write_all(
+            b"# team keys\n0x33DA433D4E51390A424791D0A68B005A025B638F\n\nphil@example.com # phil\n",
+        )?;
+
+        let recipients = super::read_gpg_id(&gpg_id_path)?;
+        assert_eq!(
+            recipients,
+            vec![
+                "0x33DA433D4E51390A424791D0A68B005A025B638F".to_string(),
+                "phil@example.com".to_string()
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_gpg_id() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let store = tmp_dir.path();
+
+        std::fs::create_dir_all(store.join("team").join("servers"))?;
+        File::create(store.join(".gpg-id"))?.write_all(b"phil@example.com\n")?;
+        File::create(store.join("team").join(".gpg-id"))?.write_all(b"team@example.com\n")?;
+
+        // Entry at the root of the store
+        let path = super::find_gpg_id(store, &store.join("password.gpg"))?;
+        assert_eq!(path, store.join(".gpg-id"));
+
+        // Entry in a folder with its own `.gpg-id`
+        let path = super::find_gpg_id(store, &store.join("team").join("password.gpg"))?;
+        assert_eq!(path, store.join("team").join(".gpg-id"));
+
+        // Entry in a subfolder, use nearest `.gpg-id`
+        let entry = store.join("team").join("servers").join("password.gpg");
+        let path = super::find_gpg_id(store, &entry)?;
+        assert_eq!(path, store.join("team").join(".gpg-id"));
+
+        // Entry outside of the store
+        assert!(super::find_gpg_id(&store.join("team"), &store.join("password.gpg")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_verify_gpg_id() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let gpg_id_path = tmp_dir.path().join(".gpg-id");
+        let certs = super::load_certs(secret_key_path().as_ref())?;
+
+        File::create(&gpg_id_path)?.write_all(b"phil@example.com\n")?;
+
+        let signing_keys = vec![super::parse_fingerprint(
+            "AF6DF58D346543BD06CFFE9922A2542A924C5648",
+        )?];
+        let other_keys = vec![super::parse_fingerprint(
+            "0123456789ABCDEF0123456789ABCDEF01234567",
+        )?];
+        // Fingerprint of a subkey, not of the primary key
+        let subkeys = vec![super::parse_fingerprint(
+            "0x33DA433D4E51390A424791D0A68B005A025B638F",
+        )?];
+
+        // Not signed yet
+        assert!(super::verify_file(&gpg_id_path, &signing_keys, &certs).is_err());
+
+        super::sign_file(
+            &gpg_id_path,
+            secret_key_path().as_ref(),
+            Some("password"),
+            &signing_keys,
+        )?;
+        super::verify_file(&gpg_id_path, &signing_keys, &certs)?;
+
+        // Signed by a key which is not allowed
+        assert!(super::verify_file(&gpg_id_path, &other_keys, &certs).is_err());
+        assert!(super::verify_file(&gpg_id_path, &subkeys, &certs).is_err());
+
+        // Tampered `.gpg-id`
+        File::create(&gpg_id_path)?.write_all(b"phil@example.com\nmallory@example.com\n")?;
+        assert!(super::verify_file(&gpg_id_path, &signing_keys, &certs).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_fingerprint() -> anyhow::Result<()> {
+        let fingerprint = super::parse_fingerprint("0xAF6DF58D346543BD06CFFE9922A2542A924C5648")?;
+        assert_eq!(
+            fingerprint.to_hex(),
+            "AF6DF58D346543BD06CFFE9922A2542A924C5648"
+        );
+
+        // Key ids and emails do not designate a single key
+        for key in &["22A2542A924C5648", "924C5648", "phil@example.com", "0x"] {
+            assert_eq!(super::parse_fingerprint(key).is_err(), true);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_recipients() -> anyhow::Result<()> {
+        let certs = super::load_certs(secret_key_path().as_ref())?;
+
+        // Fingerprint of subkey, fingerprint of primary key, long key ids and emails
+        for recipient in &[
+            "0x33DA433D4E51390A424791D0A68B005A025B638F",
+            "AF6DF58D346543BD06CFFE9922A2542A924C5648",
+            "22A2542A924C5648",
+            "0xA68B005A025B638F",
+            "phil@example.com",
+            "<Phil@Example.com>",
+        ] {
+            let resolved = super::resolve_recipients(&[recipient.to_string()], &certs, &[])?;
+            assert_eq!(resolved.len(), 1);
+
+            // Keys of the keyring are used too
+            let resolved = super::resolve_recipients(&[recipient.to_string()], &[], &certs)?;
+            assert_eq!(resolved.len(), 1);
+        }
+
+        // Unknown recipients
+        assert!(super::resolve_recipients(&["nobody@example.com".into()], &certs, &[]).is_err());
+        assert!(super::resolve_recipients(&["0xDEADBEEFDEADBEEF".into()], &certs, &[]).is_err());
+
+        // Short key ids are refused
+        assert!(super::resolve_recipients(&["924C5648".into()], &certs, &[]).is_err());
+        assert!(super::resolve_recipients(&["0x924C5648".into()], &[], &certs).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_ambiguous_recipients() -> anyhow::Result<()> {
+        let own_certs = super::load_certs(secret_key_path().as_ref())?;
+
+        // Another key with the same email, planted in the keyring
+        let (planted, _) =
+            CertBuilder::general_purpose(None, Some("phil@example.com")).generate()?;
+        let keyring = vec![planted.clone(), own_certs[0].clone()];
+
+        // Own key is used before the keyring
+        let resolved =
+            super::resolve_recipients(&["phil@example.com".into()], &own_certs, &keyring)?;
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].fingerprint(), own_certs[0].fingerprint());
+
+        // Keys of the keyring can not be told apart by email
+        assert!(super::resolve_recipients(&["phil@example.com".into()], &[], &keyring).is_err());
+
+        // But they can by fingerprint
+        let resolved =
+            super::resolve_recipients(&[planted.fingerprint().to_hex()], &own_certs, &keyring)?;
+        assert_eq!(resolved[0].fingerprint(), planted.fingerprint());
+
+        Ok(())
+    }
+}
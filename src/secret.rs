@@ -0,0 +1,214 @@
+use anyhow::Context;
+use sequoia_openpgp::crypto;
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::ops::Deref;
+
+/// Text of a secret (password, passphrase), kept in locked memory and wiped when dropped
+///
+/// Locked memory is not written to swap, if the OS allows it to be locked
+pub struct SecretString {
+    // Valid UTF-8, only grows through `reserve` to not leave copies behind
+    bytes: Vec<u8>,
+
+    // `bytes` is locked in memory
+    locked: bool,
+}
+
+impl SecretString {
+    /// Take the buffer of `bytes` without copying it
+    fn from_bytes(mut bytes: Vec<u8>) -> Self {
+        let locked =
+            bytes.capacity() > 0 && unsafe { memsec::mlock(bytes.as_mut_ptr(), bytes.capacity()) };
+
+        Self { bytes, locked }
+    }
+
+    /// Take the buffer of `bytes` if they are valid UTF-8, wipe them otherwise
+    pub fn from_utf8(bytes: Vec<u8>) -> anyhow::Result<Self> {
+        let secret = Self::from_bytes(bytes);
+        std::str::from_utf8(&secret.bytes).context("Secret is not valid UTF-8")?;
+
+        Ok(secret)
+    }
+
+    /// Empty secret, able to hold `capacity` bytes without moving
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::from_bytes(Vec::with_capacity(capacity))
+    }
+
+    /// Make room for `additional` bytes
+    ///
+    /// The secret is moved to a bigger buffer, and the old one is wiped
+    fn reserve(&mut self, additional: usize) {
+        let needed = self.bytes.len() + additional;
+        if needed <= self.bytes.capacity() {
+            return;
+        }
+
+        let mut bigger = Self::with_capacity(needed.max(self.bytes.capacity() * 2));
+        bigger.bytes.extend_from_slice(&self.bytes);
+        std::mem::swap(self, &mut bigger);
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        self.reserve(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        let capacity = self.bytes.capacity();
+        unsafe {
+            memsec::memzero(self.bytes.as_mut_ptr(), capacity);
+            if self.locked {
+                memsec::munlock(self.bytes.as_mut_ptr(), capacity);
+            }
+        }
+    }
+}
+
+impl Deref for SecretString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        // Only valid UTF-8 is put in `bytes`
+        unsafe { std::str::from_utf8_unchecked(&self.bytes) }
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SecretString(<hidden>)")
+    }
+}
+
+impl Default for SecretString {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl Clone for SecretString {
+    fn clone(&self) -> Self {
+        Self::from(&**self)
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(s: String) -> Self {
+        Self::from_bytes(s.into_bytes())
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(s: &str) -> Self {
+        let mut secret = Self::with_capacity(s.len());
+        secret.push_str(s);
+
+        secret
+    }
+}
+
+impl From<SecretString> for crypto::Password {
+    fn from(secret: SecretString) -> Self {
+        secret.as_bytes().into()
+    }
+}
+
+#[cfg(test)]
+impl PartialEq<&str> for SecretString {
+    fn eq(&self, other: &&str) -> bool {
+        &**self == *other
+    }
+}
+
+/// Read all of `reader` as text, without leaving copies of it in memory
+pub fn read_to_secret(reader: &mut dyn Read) -> anyhow::Result<SecretString> {
+    let mut bytes = SecretString::with_capacity(4096);
+    let mut chunk = [0u8; 4096];
+
+    let result = loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => break Ok(()),
+            Ok(n) => {
+                bytes.reserve(n);
+                bytes.bytes.extend_from_slice(&chunk[..n]);
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        }
+    };
+    unsafe { memsec::memzero(chunk.as_mut_ptr(), chunk.len()) };
+    result?;
+
+    // Dropping `bytes` wipes it if it is not text
+    std::str::from_utf8(&bytes.bytes).context("Secret is not valid UTF-8")?;
+
+    Ok(bytes)
+}
+
+/// Keep Windows Error Reporting from being invoked when the process crashes, so that
+/// the memory of the process, and the secrets in it, are not sent in a crash report
+///
+/// Only the current process is changed, nothing is written to the registry
+#[cfg(windows)]
+pub fn disable_core_dumps() {
+    use winapi::um::errhandlingapi::SetErrorMode;
+    use winapi::um::winbase::{SEM_FAILCRITICALERRORS, SEM_NOGPFAULTERRORBOX};
+
+    unsafe {
+        SetErrorMode(SEM_FAILCRITICALERRORS | SEM_NOGPFAULTERRORBOX);
+    }
+}
+
+/// Prevent the memory of the process, and the secrets in it, from being written in a core dump
+#[cfg(unix)]
+pub fn disable_core_dumps() {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    unsafe {
+        libc::setrlimit(libc::RLIMIT_CORE, &limit);
+    }
+
+    // Also keeps other processes of the user from reading our memory
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecretString;
+
+    #[test]
+    fn grow() {
+        let mut secret = SecretString::with_capacity(2);
+        secret.push_str("my_");
+        secret.push_str("password");
+        secret.push('\n');
+        assert_eq!(secret, "my_password\n");
+
+        assert_eq!(secret.clone(), "my_password\n");
+    }
+
+    #[test]
+    fn read_to_secret() -> anyhow::Result<()> {
+        let contents = "line\n".repeat(2000);
+        let secret = super::read_to_secret(&mut contents.as_bytes())?;
+        assert_eq!(secret, contents.as_str());
+
+        let result = super::read_to_secret(&mut &[0xff, 0xfe][..]);
+        assert_eq!(result.is_err(), true);
+
+        Ok(())
+    }
+}
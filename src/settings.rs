@@ -1,10 +1,36 @@
-use crate::constants::{ID_APPLICATION, ID_ORGANIZATION, ID_QUALIFIER, SETTINGS_FILE};
+use crate::constants::{
+    AGENT_TIMEOUT, ID_APPLICATION, ID_ORGANIZATION, ID_QUALIFIER, KEY_EXTENSIONS, SETTINGS_FILE,
+};
+use crate::recipients::parse_fingerprint;
 use anyhow::{anyhow, Context};
 use directories_next::ProjectDirs;
+use sequoia_openpgp::Fingerprint;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Where the secret key is used from
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyBackend {
+    /// Secret key is in the file `pgp_key`
+    File,
+
+    /// Secret key is in gpg-agent, `pgp_key` can be the public key
+    GpgAgent,
+}
+
+/// Check if the extension of `path` is the one of a key file, like `.asc`
+pub fn is_key_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(extension) => KEY_EXTENSIONS
+            .iter()
+            .any(|key_extension| extension.eq_ignore_ascii_case(key_extension)),
+        None => false,
+    }
+}
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct Settings {
@@ -13,6 +39,33 @@ pub struct Settings {
 
     password_store: Option<PathBuf>,
     pgp_key: Option<PathBuf>,
+
+    // Sign passwords with the pgp key when encrypting them
+    sign_passwords: Option<bool>,
+
+    // Keys trusted to sign passwords, in addition to the pgp key (full fingerprints of primary keys)
+    trusted_signers: Option<Vec<String>>,
+
+    // Refuse to use passwords without a good signature from a trusted key
+    require_signature: Option<bool>,
+
+    // Other secret keys to decrypt passwords, files or folders of files
+    secret_keys: Option<Vec<PathBuf>>,
+
+    // Command printing the password of the secret key
+    passphrase_command: Option<String>,
+
+    // Seconds before the agent forgets an unused key
+    agent_timeout: Option<u64>,
+
+    // Decrypt with the secret key from the file or from gpg-agent
+    key_backend: Option<KeyBackend>,
+
+    // Home folder of gnupg, to find the socket of gpg-agent
+    gnupg_home: Option<PathBuf>,
+
+    // Pinentry program asking passwords and confirmations, instead of the terminal
+    pinentry: Option<PathBuf>,
 }
 
 impl Settings {
@@ -108,7 +161,7 @@ impl Settings {
 
     pub fn get_password_store_path(&self) -> Result<&Path, anyhow::Error> {
         match &self.password_store {
-            Some(path) => Ok(&path),
+            Some(path) => Ok(path),
             None => Err(anyhow!("Password store is not initiated")),
         }
     }
@@ -119,14 +172,106 @@ impl Settings {
 
     pub fn get_pgp_key_path(&self) -> Result<&Path, anyhow::Error> {
         match &self.pgp_key {
-            Some(path) => Ok(&path),
+            Some(path) => Ok(path),
             None => Err(anyhow!("Password store is not initiated")),
         }
     }
 
+    /// Paths of all key files: `pgp_key` followed by the files of `secret_keys`
+    pub fn get_key_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = vec![self.get_pgp_key_path()?.to_path_buf()];
+
+        for path in self.secret_keys.iter().flatten() {
+            if path.is_dir() {
+                // Files of the folder with the extension of a key, others may be notes or backups
+                let mut files = std::fs::read_dir(path)
+                    .with_context(|| format!("Failed to read folder {}", path.display()))?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                files.retain(|file| file.is_file() && is_key_file(file));
+                files.sort();
+                paths.append(&mut files);
+            } else {
+                paths.push(path.clone());
+            }
+        }
+
+        Ok(paths)
+    }
+
+    #[cfg(test)]
+    pub fn set_secret_keys(&mut self, paths: Vec<PathBuf>) {
+        self.secret_keys = Some(paths);
+    }
+
+    pub fn get_sign_passwords(&self) -> bool {
+        self.sign_passwords.unwrap_or(false)
+    }
+
+    #[cfg(test)]
+    pub fn set_sign_passwords(&mut self, sign_passwords: bool) {
+        self.sign_passwords = Some(sign_passwords);
+    }
+
+    /// Fingerprints of the keys trusted to sign passwords
+    ///
+    /// Like `PASSWORD_STORE_SIGNING_KEY`, only full fingerprints of primary keys are accepted
+    pub fn get_trusted_signers(&self) -> anyhow::Result<Vec<Fingerprint>> {
+        self.trusted_signers
+            .iter()
+            .flatten()
+            .map(|key| parse_fingerprint(key).context("Invalid key in trusted_signers"))
+            .collect()
+    }
+
+    #[cfg(test)]
+    pub fn set_trusted_signers(&mut self, signers: &[&str]) {
+        self.trusted_signers = Some(signers.iter().map(|s| s.to_string()).collect());
+    }
+
+    #[cfg(test)]
+    pub fn set_require_signature(&mut self, require_signature: bool) {
+        self.require_signature = Some(require_signature);
+    }
+
+    pub fn get_require_signature(&self) -> bool {
+        self.require_signature.unwrap_or(false)
+    }
+
+    pub fn get_passphrase_command(&self) -> Option<&str> {
+        self.passphrase_command.as_deref()
+    }
+
+    #[cfg(test)]
+    pub fn set_passphrase_command(&mut self, command: &str) {
+        self.passphrase_command = Some(command.to_string());
+    }
+
+    pub fn get_agent_timeout(&self) -> Duration {
+        Duration::from_secs(self.agent_timeout.unwrap_or(AGENT_TIMEOUT))
+    }
+
+    pub fn get_key_backend(&self) -> KeyBackend {
+        self.key_backend.unwrap_or(KeyBackend::File)
+    }
+
+    pub fn get_gnupg_home(&self) -> Option<&Path> {
+        self.gnupg_home.as_deref()
+    }
+
+    #[cfg(test)]
+    pub fn set_gpg_agent(&mut self, gnupg_home: &Path) {
+        self.key_backend = Some(KeyBackend::GpgAgent);
+        self.gnupg_home = Some(PathBuf::from(gnupg_home));
+    }
+
+    pub fn get_pinentry(&self) -> Option<&Path> {
+        self.pinentry.as_deref()
+    }
+
     pub fn get_settings_path(&self) -> Result<&Path, anyhow::Error> {
         match &self.path {
-            Some(path) => Ok(&path),
+            Some(path) => Ok(path),
             None => Err(anyhow!("Settings file does not exist")),
         }
     }
@@ -142,6 +287,34 @@ impl Settings {
             self.get_password_store_path()?.display()
         );
         println!("PGP Key location: {}", self.get_pgp_key_path()?.display());
+        for path in self.get_key_paths()?.iter().skip(1) {
+            println!("Other secret key location: {}", path.display());
+        }
+        println!("Sign passwords: {}", self.get_sign_passwords());
+        let trusted_signers = self
+            .get_trusted_signers()?
+            .iter()
+            .map(|fingerprint| fingerprint.to_hex())
+            .collect::<Vec<_>>();
+        println!("Trusted signers: {}", trusted_signers.join(", "));
+        println!("Require signature: {}", self.get_require_signature());
+        if let Some(command) = self.get_passphrase_command() {
+            println!("Passphrase command: {}", command);
+        }
+        println!(
+            "Agent timeout: {} seconds",
+            self.get_agent_timeout().as_secs()
+        );
+        match self.get_key_backend() {
+            KeyBackend::File => println!("Key backend: file"),
+            KeyBackend::GpgAgent => println!("Key backend: gpg-agent"),
+        }
+        if let Some(gnupg_home) = self.get_gnupg_home() {
+            println!("GnuPG home: {}", gnupg_home.display());
+        }
+        if let Some(pinentry) = self.get_pinentry() {
+            println!("Pinentry: {}", pinentry.display());
+        }
 
         Ok(())
     }
@@ -169,3 +342,43 @@ fn construct_path_from_app_data() -> anyhow::Result<PathBuf> {
 
     Ok(path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Settings;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn key_paths() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let keys = tmp_dir.path().join("keys");
+        std::fs::create_dir(&keys)?;
+        for name in &[
+            "team.asc",
+            "other.GPG",
+            "readme.txt",
+            "backup.asc.bak",
+            "notes",
+        ] {
+            File::create(keys.join(name))?;
+        }
+        std::fs::create_dir(keys.join("old.asc"))?;
+
+        let mut settings = Settings::default();
+        settings.set_pgp_key_path(&tmp_dir.path().join("personal.asc"));
+        settings.set_secret_keys(vec![keys.clone(), tmp_dir.path().join("single.key")]);
+
+        assert_eq!(
+            settings.get_key_paths()?,
+            vec![
+                tmp_dir.path().join("personal.asc"),
+                keys.join("other.GPG"),
+                keys.join("team.asc"),
+                tmp_dir.path().join("single.key"),
+            ]
+        );
+
+        Ok(())
+    }
+}
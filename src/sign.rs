@@ -0,0 +1,23 @@
+use crate::decrypt::unlock_key;
+use anyhow::Context;
+use sequoia_openpgp::crypto::KeyPair;
+use sequoia_openpgp::policy::StandardPolicy;
+use sequoia_openpgp::Cert;
+
+/// Get a usable signing key of `cert`, decrypted with `password` (or asks for one)
+pub fn signing_keypair(cert: &Cert, password: Option<&str>) -> anyhow::Result<KeyPair> {
+    let policy = &StandardPolicy::new();
+
+    let secret_key = cert
+        .keys()
+        .with_policy(policy, None)
+        .for_signing()
+        .secret()
+        .alive()
+        .revoked(false)
+        .map(|key| key.key().clone())
+        .next()
+        .context("Failed to find a secret signing key")?;
+
+    unlock_key(secret_key, password, "sign")
+}
@@ -14,7 +14,7 @@ pub fn init_repo(path: &Path) -> anyhow::Result<Repository> {
 }
 
 /// Write index to a git tree
-fn write_index_to_tree(repo: &Repository) -> Result<Tree, Error> {
+fn write_index_to_tree(repo: &Repository) -> Result<Tree<'_>, Error> {
     // Create tree from current index
     let tree = {
         // Get repo index
@@ -29,7 +29,7 @@ fn write_index_to_tree(repo: &Repository) -> Result<Tree, Error> {
 }
 
 /// Get current git signature or create one
-fn get_signature(repo: &Repository) -> Result<Signature, Error> {
+fn get_signature(repo: &Repository) -> Result<Signature<'_>, Error> {
     // Try to get local signature
     if let Ok(sig) = repo.signature() {
         return Ok(sig);
@@ -57,7 +57,7 @@ fn create_initial_commit(repo: &Repository) -> Result<(), Error> {
 }
 
 /// Get HEAD commit
-pub fn get_head_commit(repo: &Repository) -> Result<Commit, Error> {
+pub fn get_head_commit(repo: &Repository) -> Result<Commit<'_>, Error> {
     let obj = repo.head()?.resolve()?.peel(ObjectType::Commit)?;
     obj.into_commit()
         .map_err(|_| Error::from_str("Failed to find HEAD commit"))
@@ -128,7 +128,7 @@ fn add_file_commit_with_message(
     path: &Path,
     message: &str,
 ) -> Result<(), anyhow::Error> {
-    let relative_path_file = match get_relative_path(&repo, &path) {
+    let relative_path_file = match get_relative_path(repo, path) {
         Some(file_path) => file_path,
         None => {
             return Err(anyhow!(
@@ -138,9 +138,9 @@ fn add_file_commit_with_message(
         }
     };
 
-    add_path(&repo, &relative_path_file)?;
+    add_path(repo, &relative_path_file)?;
     let commit_msg = format!("{} {}", message, relative_path_file.display());
-    create_commit(&repo, &commit_msg)?;
+    create_commit(repo, &commit_msg)?;
 
     Ok(())
 }
@@ -161,6 +161,22 @@ pub fn add_commit_update_password(repo: &Repository, password: &Password) -> any
     add_file_commit_with_message(repo, path, "Updated password")
 }
 
+/// Commit changes of the fields `names` of a password, their values are not in the message
+pub fn add_commit_fields(
+    repo: &Repository,
+    password: &Password,
+    action: &str,
+    names: &[&str],
+) -> anyhow::Result<()> {
+    let path = password
+        .get_filepath()
+        .context("Path of password is not set (this should not happen)")?;
+
+    let plural = if names.len() > 1 { "s" } else { "" };
+    let message = format!("{} field{} {} of", action, plural, names.join(", "));
+    add_file_commit_with_message(repo, path, &message)
+}
+
 pub fn add_commit_file(repo: &Repository, path: &Path) -> anyhow::Result<()> {
     add_file_commit_with_message(repo, path, "Added file")
 }
@@ -207,7 +223,7 @@ mod tests {
         assert_eq!(repo.index()?.is_empty(), true);
 
         // add file to index
-        super::add_path(&repo, &relative_path)?;
+        super::add_path(&repo, relative_path)?;
         assert_eq!(repo.index()?.is_empty(), false);
         println!("added file to index {}", file_path.display());
 
@@ -232,7 +248,7 @@ mod tests {
         }
 
         // add file to index
-        super::add_path(&repo, &relative_path)?;
+        super::add_path(&repo, relative_path)?;
         println!("added file to index {}", file_path.display());
 
         // commit new file
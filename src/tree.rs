@@ -1,6 +1,7 @@
+use std::ffi::OsStr;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn add_corner_current_line(str: &mut String, cur_elem: usize, num_elements: usize) {
     if cur_elem + 1 == num_elements {
@@ -81,3 +82,31 @@ pub fn tree(path: &PathBuf) -> Option<String> {
 
     Some(string)
 }
+
+/// Get all password files at `path`, which is a password file or a folder
+pub fn list_entries(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+
+    if path.is_file() {
+        entries.push(path.to_path_buf());
+        return Ok(entries);
+    }
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let entry_path = entry.path();
+
+        // Skip hidden folders / files (such as `.git`)
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        if entry_path.is_dir() {
+            entries.append(&mut list_entries(&entry_path)?);
+        } else if entry_path.extension() == Some(OsStr::new("gpg")) {
+            entries.push(entry_path);
+        }
+    }
+
+    Ok(entries)
+}